@@ -0,0 +1,213 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{
+    io,
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use futures::Future;
+
+use crate::{
+    link::{self, BleHdlMsg, BleSerialRes},
+    transport::GattTransport,
+    BleSerialBuilder, BleSerialError, DeviceSelector, LinkHandle,
+};
+
+/// Asynchronous counterpart of [`crate::BleSerial`], running on the caller's tokio runtime.
+pub struct AsyncBleSerial {
+    link: LinkHandle,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl AsyncBleSerial {
+    /// Spawns the background task on the current tokio runtime; panics if called
+    /// outside of a tokio runtime.
+    pub fn build(device: impl Into<DeviceSelector>) -> Self {
+        BleSerialBuilder::new(device).build_async()
+    }
+
+    /// Like [`AsyncBleSerial::build`], but works with the given transport.
+    pub fn build_with_transport<T: GattTransport>(
+        transport: T,
+        device: impl Into<DeviceSelector>,
+    ) -> Self {
+        BleSerialBuilder::new(device).build_async_with_transport(transport)
+    }
+
+    pub(crate) fn build_inner<F: Future<Output = ()> + Send + 'static>(
+        res: BleSerialRes,
+        f_loop: impl FnOnce(Arc<Mutex<BleSerialRes>>) -> F,
+    ) -> Self {
+        let res = Arc::new(Mutex::new(res));
+        let task = tokio::spawn(f_loop(res.clone()));
+        Self {
+            link: LinkHandle { res },
+            task,
+        }
+    }
+
+    pub async fn set_baud_rate(&self, baud: u32) -> Result<u32, BleSerialError> {
+        let (expected, baud_set_timeout) = {
+            let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+            // impossible baud rates are rejected without asking the device
+            let Some(expected) = link::expected_baud(baud) else {
+                return Err(lck_res.baud_rejected(baud));
+            };
+            if !lck_res.send_req(BleHdlMsg::ReqSetBaud(baud)) {
+                return Err(BleSerialError::Disconnected);
+            }
+            (expected, lck_res.config.baud_set_timeout)
+        };
+
+        let t_end = tokio::time::Instant::now() + baud_set_timeout;
+        while tokio::time::Instant::now() < t_end {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let cur_baud = self
+                .res
+                .lock()
+                .map_err(|_| BleSerialError::Poisoned)?
+                .baud_rate;
+            if cur_baud == expected {
+                return Ok(cur_baud);
+            }
+        }
+        let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        Err(lck_res.baud_rejected(baud))
+    }
+}
+
+impl Deref for AsyncBleSerial {
+    type Target = LinkHandle;
+
+    fn deref(&self) -> &LinkHandle {
+        &self.link
+    }
+}
+
+impl AsyncRead for AsyncBleSerial {
+    /// Pending until some data is available; reaches the end of file once the buffer
+    /// is empty and the background task has ended.
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        if lck_res.read_ended() {
+            return Poll::Ready(Ok(())); // end of file
+        }
+        if lck_res.buf_read.is_empty() {
+            lck_res.read_waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let cnt = buf.remaining().min(lck_res.buf_read.len());
+        let cnt = lck_res.take_read(buf.initialize_unfilled_to(cnt));
+        buf.advance(cnt);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncBleSerial {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        if lck_res.dev_name.is_none() {
+            return Poll::Ready(Err(BleSerialError::Disconnected.into()));
+        }
+        let room = lck_res.write_room();
+        if room == 0 {
+            lck_res.write_waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let cnt = room.min(buf.len());
+        if !lck_res.queue_write(buf[..cnt].to_vec()) {
+            return Poll::Ready(Err(BleSerialError::Disconnected.into()));
+        }
+        Poll::Ready(Ok(cnt))
+    }
+
+    /// Pending until all queued bytes are sent to the device. Fails with the bytes
+    /// that couldn't be sent since the last call, including bytes dropped on disconnection.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        if !lck_res.check_flushed()? {
+            lck_res.write_waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl Drop for AsyncBleSerial {
+    fn drop(&mut self) {
+        debug!("AsyncBleSerial::drop(): entered.");
+        // the runtime isn't owned here, so the task must be stopped even if it's
+        // still discovering the device
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{fake::FakePeripheral, AsyncBleSerial, DeviceSelector};
+
+    #[tokio::test]
+    async fn loopback() {
+        let fake = FakePeripheral::default();
+        fake.set_loopback(true);
+        let mut ble_ser =
+            AsyncBleSerial::build_with_transport(fake.transport(), DeviceSelector::FirstFound);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !ble_ser.is_connected() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        ble_ser.write_all(b"hello").await.unwrap();
+        ble_ser.flush().await.unwrap();
+        let mut buf = [0u8; 5];
+        ble_ser.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // a pending read is woken by received data
+        let fake_rx = fake.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            fake_rx.push_uart_rx(b"late");
+        });
+        let mut buf = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), ble_ser.read_exact(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf, b"late");
+    }
+}
@@ -0,0 +1,239 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Transport working with BlueZ through `bluer` directly, which makes it possible
+//! to choose an adapter other than the default one.
+
+use bluer::{
+    gatt::{
+        remote::{Characteristic, CharacteristicWriteRequest},
+        WriteOp,
+    },
+    AdapterEvent, AdapterProperty,
+};
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    transport::{
+        subscribed, Advertisement, BoxStream, GattCharacteristic, GattDevice, GattTransport,
+        TransportError, TransportResult,
+    },
+    AdapterSelector,
+};
+
+impl From<bluer::Error> for TransportError {
+    fn from(e: bluer::Error) -> Self {
+        Self::new(e.to_string())
+    }
+}
+
+/// Transport backed by a BlueZ adapter chosen by name or index (Linux only).
+#[derive(Clone)]
+pub struct BluerTransport {
+    // the session must be kept alive for the adapter
+    _session: bluer::Session,
+    adapter: bluer::Adapter,
+}
+
+impl BluerTransport {
+    /// Opens the adapter chosen by `selector`; adapters are indexed in the order of names.
+    pub async fn open(selector: &AdapterSelector) -> Option<Self> {
+        let session = bluer::Session::new().await.ok()?;
+        let adapter = match selector {
+            AdapterSelector::Default => session.default_adapter().await.ok()?,
+            AdapterSelector::Name(name) => {
+                let names = session.adapter_names().await.ok()?;
+                names.contains(name).then(|| session.adapter(name).ok())??
+            }
+            AdapterSelector::Index(i) => {
+                let mut names = session.adapter_names().await.ok()?;
+                names.sort();
+                session.adapter(names.get(*i)?).ok()?
+            }
+        };
+        Some(Self {
+            _session: session,
+            adapter,
+        })
+    }
+
+    /// Name of the adapter, like `hci0`.
+    pub fn adapter_name(&self) -> &str {
+        self.adapter.name()
+    }
+
+    fn open_device(&self, addr: bluer::Address) -> TransportResult<bluer::Device> {
+        Ok(self.adapter.device(addr)?)
+    }
+}
+
+impl GattTransport for BluerTransport {
+    type Device = bluer::Device;
+
+    async fn wait_available(&self) -> TransportResult<()> {
+        let mut events = self.adapter_events().await?;
+        if self.adapter.is_powered().await? {
+            return Ok(());
+        }
+        while let Some(powered) = events.next().await {
+            if powered {
+                return Ok(());
+            }
+        }
+        Err(TransportError::new("adapter removed"))
+    }
+
+    async fn adapter_events(&self) -> TransportResult<BoxStream<'_, bool>> {
+        let stream = self.adapter.events().await?;
+        Ok(Box::pin(stream.filter_map(|evt| async move {
+            match evt {
+                AdapterEvent::PropertyChanged(AdapterProperty::Powered(powered)) => Some(powered),
+                _ => None,
+            }
+        })))
+    }
+
+    async fn discover_devices<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, TransportResult<bluer::Device>>> {
+        let stream = self.adapter.discover_devices().await?;
+        Ok(Box::pin(stream.filter_map(move |evt| async move {
+            let AdapterEvent::DeviceAdded(addr) = evt else {
+                return None;
+            };
+            let device = match self.open_device(addr) {
+                Ok(device) => device,
+                Err(e) => return Some(Err(e)),
+            };
+            match device.uuids().await {
+                Ok(uuids) => {
+                    let uuids = uuids.unwrap_or_default();
+                    (services.is_empty() || services.iter().any(|s| uuids.contains(s)))
+                        .then_some(Ok(device))
+                }
+                Err(e) => Some(Err(e.into())),
+            }
+        })))
+    }
+
+    async fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, Advertisement<bluer::Device>>> {
+        let stream = self.adapter.discover_devices_with_changes().await?;
+        Ok(Box::pin(stream.filter_map(move |evt| async move {
+            let AdapterEvent::DeviceAdded(addr) = evt else {
+                return None;
+            };
+            let device = self.open_device(addr).ok()?;
+            if device.is_connected().await.unwrap_or(false) {
+                return None;
+            }
+            let uuids = device.uuids().await.ok().flatten().unwrap_or_default();
+            if !services.is_empty() && !services.iter().any(|s| uuids.contains(s)) {
+                return None;
+            }
+            let local_name = device.alias().await.ok().filter(|s| !s.is_empty());
+            let rssi = device.rssi().await.ok().flatten();
+            Some(Advertisement {
+                device,
+                local_name,
+                rssi,
+                is_connectable: None, // not exposed by BlueZ
+            })
+        })))
+    }
+
+    async fn connect_device(&self, device: &bluer::Device) -> TransportResult<()> {
+        Ok(device.connect().await?)
+    }
+}
+
+impl GattDevice for bluer::Device {
+    type Characteristic = BluerCharacteristic;
+
+    fn id(&self) -> String {
+        self.address().to_string()
+    }
+
+    async fn name(&self) -> TransportResult<String> {
+        Ok(self.alias().await?)
+    }
+
+    async fn is_connected(&self) -> bool {
+        bluer::Device::is_connected(self).await.unwrap_or(false)
+    }
+
+    async fn service_characteristics(
+        &self,
+        service: Uuid,
+    ) -> TransportResult<Option<Vec<BluerCharacteristic>>> {
+        for serv in self.services().await? {
+            if serv.uuid().await? != service {
+                continue;
+            }
+            let mut chars = Vec::new();
+            for inner in serv.characteristics().await? {
+                let uuid = inner.uuid().await?;
+                chars.push(BluerCharacteristic { inner, uuid });
+            }
+            return Ok(Some(chars));
+        }
+        Ok(None)
+    }
+}
+
+/// GATT characteristic of [`BluerTransport`].
+#[derive(Clone)]
+pub struct BluerCharacteristic {
+    inner: Characteristic,
+    uuid: Uuid, // `bluer` reads it asynchronously
+}
+
+impl GattCharacteristic for BluerCharacteristic {
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    async fn read(&self) -> TransportResult<Vec<u8>> {
+        Ok(self.inner.read().await?)
+    }
+
+    async fn write(&self, data: &[u8]) -> TransportResult<()> {
+        Ok(self.inner.write(data).await?)
+    }
+
+    async fn write_without_response(&self, data: &[u8]) -> TransportResult<()> {
+        let req = CharacteristicWriteRequest {
+            op_type: WriteOp::Command,
+            ..Default::default()
+        };
+        Ok(self.inner.write_ext(data, &req).await?)
+    }
+
+    async fn max_write_len(&self) -> TransportResult<usize> {
+        Ok(self.inner.mtu().await?.saturating_sub(3).max(1))
+    }
+
+    async fn notify(&self) -> TransportResult<BoxStream<'static, TransportResult<Vec<u8>>>> {
+        // BlueZ writes the client characteristic configuration descriptor by itself
+        let ch = self.inner.clone();
+        subscribed(Box::pin(async_stream::stream! {
+            let stream_notify = match ch.notify().await {
+                Ok(s) => s,
+                Err(e) => {
+                    yield Err(TransportError::from(e));
+                    return;
+                }
+            };
+            yield Ok(None);
+            let mut stream_notify = Box::pin(stream_notify);
+            while let Some(item) = stream_notify.next().await {
+                yield Ok(Some(item));
+            }
+        }))
+        .await
+    }
+}
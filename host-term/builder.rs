@@ -0,0 +1,180 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::Future;
+
+use crate::{
+    link::{self, BleSerialRes, LinkConfig},
+    transport::GattTransport,
+    AdapterSelector, AsyncBleSerial, BleSerial, BleSerialError, DeviceSelector, ReadOverflow,
+    ReconnectPolicy, WriteMode,
+};
+
+/// Configures and builds [`BleSerial`] or [`AsyncBleSerial`].
+#[derive(Debug, Clone)]
+pub struct BleSerialBuilder {
+    selector: DeviceSelector,
+    adapter: AdapterSelector,
+    read_timeout: Duration,
+    worker_threads: usize,
+    config: LinkConfig,
+    read_buf_capacity: usize,
+    read_overflow: ReadOverflow,
+    write_queue_limit: usize,
+    write_mode: WriteMode,
+}
+
+impl BleSerialBuilder {
+    /// Starts with the default values, connecting to the device chosen by `device`.
+    pub fn new(device: impl Into<DeviceSelector>) -> Self {
+        Self {
+            selector: device.into(),
+            adapter: AdapterSelector::Default,
+            read_timeout: Duration::from_millis(500),
+            worker_threads: 2,
+            config: LinkConfig::default(),
+            read_buf_capacity: 64 * 1024,
+            read_overflow: ReadOverflow::default(),
+            write_queue_limit: 4096,
+            write_mode: WriteMode::default(),
+        }
+    }
+
+    /// Bluetooth adapter used by `build()` and `build_async()`, the default one by default.
+    pub fn adapter(mut self, adapter: AdapterSelector) -> Self {
+        self.adapter = adapter;
+        self
+    }
+
+    /// Timeout of `io::Read::read()`, 500 ms by default.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Worker threads of the runtime created by `build()`, 2 by default.
+    pub fn worker_threads(mut self, cnt: usize) -> Self {
+        self.worker_threads = cnt.max(1);
+        self
+    }
+
+    /// Retrying forever with a delay of 1500 ms by default.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.config.reconnect = policy;
+        self
+    }
+
+    /// Time to wait for the device in each discovery, 10 s by default.
+    pub fn discovery_timeout(mut self, timeout: Duration) -> Self {
+        self.config.discovery_timeout = timeout;
+        self
+    }
+
+    /// Interval of checking the connection, 2000 ms by default.
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.config.check_interval = interval;
+        self
+    }
+
+    /// Attempts for writing each chunk of data, 3 by default.
+    pub fn write_attempts(mut self, cnt: u32) -> Self {
+        self.config.write_attempts = cnt.max(1);
+        self
+    }
+
+    /// Reads of the baud rate characteristic for verifying the new baud rate,
+    /// and the interval between them; 10 times and 400 ms by default.
+    pub fn baud_verify(mut self, attempts: u32, interval: Duration) -> Self {
+        self.config.baud_verify_attempts = attempts.max(1);
+        self.config.baud_verify_interval = interval;
+        self
+    }
+
+    /// Maximum time for `set_baud_rate()` to wait, 10 s by default.
+    pub fn baud_set_timeout(mut self, timeout: Duration) -> Self {
+        self.config.baud_set_timeout = timeout;
+        self
+    }
+
+    /// Capacity of the read buffer, 64 KiB by default.
+    pub fn read_buf_capacity(mut self, capacity: usize) -> Self {
+        self.read_buf_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn read_overflow(mut self, policy: ReadOverflow) -> Self {
+        self.read_overflow = policy;
+        self
+    }
+
+    /// Maximum amount of bytes queued but not yet sent, 4096 by default.
+    pub fn write_queue_limit(mut self, limit: usize) -> Self {
+        self.write_queue_limit = limit.max(1);
+        self
+    }
+
+    pub fn write_mode(mut self, mode: WriteMode) -> Self {
+        self.write_mode = mode;
+        self
+    }
+
+    /// Builds `BleSerial` with the system's bluetooth adapter.
+    pub fn build(self) -> Result<BleSerial, BleSerialError> {
+        let (worker_threads, read_timeout) = (self.worker_threads, self.read_timeout);
+        let adapter = self.adapter.clone();
+        BleSerial::build_inner(self.res(), worker_threads, read_timeout, |res| {
+            link::run_task(res.clone(), link::ble_loop_system_adapter(adapter, res))
+        })
+    }
+
+    /// Builds `BleSerial` working with the given transport.
+    pub fn build_with_transport<T: GattTransport>(
+        self,
+        transport: T,
+    ) -> Result<BleSerial, BleSerialError> {
+        let (worker_threads, read_timeout) = (self.worker_threads, self.read_timeout);
+        BleSerial::build_inner(self.res(), worker_threads, read_timeout, |res| {
+            link::run_task(res.clone(), link::ble_loop(transport, res))
+        })
+    }
+
+    /// Builds `AsyncBleSerial` with the system's bluetooth adapter; panics if called
+    /// outside of a tokio runtime. `read_timeout` and `worker_threads` are not used.
+    pub fn build_async(self) -> AsyncBleSerial {
+        let adapter = self.adapter.clone();
+        AsyncBleSerial::build_inner(self.res(), |res| {
+            link::run_task(res.clone(), link::ble_loop_system_adapter(adapter, res))
+        })
+    }
+
+    /// Builds `AsyncBleSerial` working with the given transport.
+    pub fn build_async_with_transport<T: GattTransport>(self, transport: T) -> AsyncBleSerial {
+        AsyncBleSerial::build_inner(self.res(), |res| {
+            link::run_task(res.clone(), link::ble_loop(transport, res))
+        })
+    }
+
+    /// Builds a handle of [`crate::BleSerialManager`] running on its runtime.
+    pub(crate) fn build_in<F: Future<Output = ()> + Send + 'static>(
+        self,
+        rt: Arc<tokio::runtime::Runtime>,
+        f_loop: impl FnOnce(Arc<Mutex<BleSerialRes>>) -> F,
+    ) -> BleSerial {
+        let read_timeout = self.read_timeout;
+        BleSerial::spawn_in(rt, self.res(), read_timeout, f_loop)
+    }
+
+    fn res(self) -> BleSerialRes {
+        let mut res = BleSerialRes::new(self.selector, self.config);
+        res.read_capacity = self.read_buf_capacity;
+        res.read_overflow = self.read_overflow;
+        res.write_queue_limit = self.write_queue_limit;
+        res.write_mode = self.write_mode;
+        res
+    }
+}
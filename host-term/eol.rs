@@ -0,0 +1,116 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Line ending translation of the terminal.
+
+use clap::ValueEnum;
+
+/// Line ending appended to each line sent in text mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TxEol {
+    Cr,
+    Lf,
+    Crlf,
+    None,
+}
+
+impl TxEol {
+    /// Replaces the line ending of a line read from stdin.
+    pub fn apply(self, line: &str) -> Vec<u8> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let eol: &[u8] = match self {
+            Self::Cr => b"\r",
+            Self::Lf => b"\n",
+            Self::Crlf => b"\r\n",
+            Self::None => b"",
+        };
+        [line.as_bytes(), eol].concat()
+    }
+}
+
+/// Translation of received line endings into LF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RxEol {
+    /// Keep the data as is
+    None,
+    /// CR to LF
+    Cr,
+    /// CRLF to LF
+    Crlf,
+}
+
+/// Translates received data; CRLF split between notifications is handled.
+pub struct RxTranslator {
+    mode: RxEol,
+    pending_cr: bool,
+}
+
+impl RxTranslator {
+    pub fn new(mode: RxEol) -> Self {
+        Self {
+            mode,
+            pending_cr: false,
+        }
+    }
+
+    pub fn translate(&mut self, data: &[u8]) -> Vec<u8> {
+        match self.mode {
+            RxEol::None => data.to_vec(),
+            RxEol::Cr => data
+                .iter()
+                .map(|&b| if b == b'\r' { b'\n' } else { b })
+                .collect(),
+            RxEol::Crlf => {
+                let mut out = Vec::with_capacity(data.len() + 1);
+                for &b in data {
+                    if self.pending_cr && b != b'\n' {
+                        out.push(b'\r');
+                    }
+                    self.pending_cr = b == b'\r';
+                    if !self.pending_cr {
+                        out.push(b);
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_eol() {
+        assert_eq!(TxEol::Cr.apply("AT\n"), b"AT\r");
+        assert_eq!(TxEol::Lf.apply("AT\r\n"), b"AT\n");
+        assert_eq!(TxEol::Crlf.apply("AT\n"), b"AT\r\n");
+        assert_eq!(TxEol::Crlf.apply("AT"), b"AT\r\n");
+        assert_eq!(TxEol::None.apply("AT\r\n"), b"AT");
+        assert_eq!(TxEol::Crlf.apply(""), b"\r\n"); // Enter in interactive mode
+    }
+
+    #[test]
+    fn rx_cr() {
+        let mut rx = RxTranslator::new(RxEol::Cr);
+        assert_eq!(rx.translate(b"a\rb\r\n"), b"a\nb\n\n");
+    }
+
+    #[test]
+    fn rx_crlf_split() {
+        let mut rx = RxTranslator::new(RxEol::Crlf);
+        assert_eq!(rx.translate(b"OK\r"), b"OK");
+        assert_eq!(rx.translate(b"\nREADY\r\n"), b"\nREADY\n");
+        // a lone CR is kept once the next byte is known
+        assert_eq!(rx.translate(b"a\r"), b"a");
+        assert_eq!(rx.translate(b"b\r\r\n"), b"\rb\r\n");
+    }
+
+    #[test]
+    fn rx_none() {
+        let mut rx = RxTranslator::new(RxEol::None);
+        assert_eq!(rx.translate(b"a\r\n"), b"a\r\n");
+    }
+}
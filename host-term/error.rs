@@ -0,0 +1,108 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{fmt, io};
+
+use uuid::Uuid;
+
+use crate::transport::TransportError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BleSerialError {
+    /// Failed to create the async runtime required by the bluetooth library.
+    Runtime,
+    AdapterNotFound,
+    AdapterPoweredOff,
+    /// The device is not found in the discovery window.
+    DeviceNotFound,
+    /// More than one device matches the selector; their ids are listed.
+    AmbiguousDevice(Vec<String>),
+    ConnectionFailed(TransportError),
+    /// The device doesn't provide service 0xA00A.
+    ServiceMissing,
+    CharacteristicMissing(Uuid),
+    NotifySubscribeFailed,
+    /// The device didn't accept the baud rate; `actual` is the baud rate in use.
+    BaudRejected {
+        requested: u32,
+        actual: u32,
+    },
+    /// Queued data couldn't be sent to the device.
+    WriteFailed {
+        undelivered: Vec<u8>,
+    },
+    /// The device is not connected, or the connection is broken.
+    Disconnected,
+    /// Other failure of a bluetooth operation.
+    Transport(TransportError),
+    /// A thread panicked while holding the internal lock.
+    Poisoned,
+    /// The background task panicked; this is a bug.
+    TaskPanicked,
+}
+
+impl fmt::Display for BleSerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Runtime => {
+                f.write_str("can't create async runtime required by the bluetooth library")
+            }
+            Self::AdapterNotFound => f.write_str("bluetooth adapter not found"),
+            Self::AdapterPoweredOff => f.write_str("bluetooth adapter is powered off"),
+            Self::DeviceNotFound => f.write_str("target device not found"),
+            Self::AmbiguousDevice(ids) => {
+                write!(f, "multiple devices matched: {}", ids.join(", "))
+            }
+            Self::ConnectionFailed(e) => write!(f, "failed to connect: {e}"),
+            Self::ServiceMissing => f.write_str("cannot find service 0xA00A"),
+            Self::CharacteristicMissing(uuid) => write!(f, "cannot find characteristic {uuid}"),
+            Self::NotifySubscribeFailed => f.write_str("failed to enable notification"),
+            Self::BaudRejected { requested, actual } => {
+                write!(f, "baud rate {requested} rejected, current: {actual}")
+            }
+            Self::WriteFailed { undelivered } => {
+                write!(f, "write failed, {} bytes not delivered", undelivered.len())
+            }
+            Self::Disconnected => f.write_str("device not connected"),
+            Self::Transport(e) => write!(f, "bluetooth error: {e}"),
+            Self::Poisoned => f.write_str("internal lock poisoned"),
+            Self::TaskPanicked => f.write_str("background task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for BleSerialError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ConnectionFailed(e) | Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for BleSerialError {
+    fn from(e: TransportError) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<BleSerialError> for io::Error {
+    fn from(e: BleSerialError) -> Self {
+        let kind = match e {
+            BleSerialError::AdapterNotFound
+            | BleSerialError::DeviceNotFound
+            | BleSerialError::ServiceMissing
+            | BleSerialError::CharacteristicMissing(_) => io::ErrorKind::NotFound,
+            BleSerialError::ConnectionFailed(_) => io::ErrorKind::ConnectionRefused,
+            BleSerialError::AmbiguousDevice(_) | BleSerialError::BaudRejected { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            BleSerialError::WriteFailed { .. } => io::ErrorKind::BrokenPipe,
+            BleSerialError::AdapterPoweredOff | BleSerialError::Disconnected => {
+                io::ErrorKind::NotConnected
+            }
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
@@ -0,0 +1,593 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! In-process fake RTL8762C peripheral emulating the 0xA00A service of the firmware,
+//! for exercising `BleSerial` without a Bluetooth adapter.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    rtlbaud,
+    transport::{
+        Advertisement, BoxStream, GattCharacteristic, GattDevice, GattTransport, TransportError,
+        TransportResult,
+    },
+    UUID_CHAR_BAUD, UUID_CHAR_READ, UUID_CHAR_WRITE, UUID_SERV,
+};
+
+struct FakeState {
+    id: String,
+    name: String,
+    present: bool,
+    rssi: i16,
+    connected: bool,
+    mtu: usize,
+    baud_actual: u32,
+    loopback: bool,
+    uart_tx: Vec<u8>,
+    ch_notify: Option<tokio::sync::mpsc::UnboundedSender<Vec<u8>>>,
+    fail_connects: u32,
+    fail_writes: u32,
+    panic_write: bool,
+}
+
+/// Controller of the fake peripheral; [`FakePeripheral::transport`] returns the
+/// [`GattTransport`] to be passed to `BleSerial::build_with_transport`.
+#[derive(Clone)]
+pub struct FakePeripheral {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl Default for FakePeripheral {
+    fn default() -> Self {
+        Self::new("00:E0:02:12:00:54", "RTL-UART-02E000")
+    }
+}
+
+impl FakePeripheral {
+    /// Creates an advertising peripheral with the given id and device name.
+    pub fn new(id: &str, name: &str) -> Self {
+        let state = FakeState {
+            id: id.to_string(),
+            name: name.to_string(),
+            present: true,
+            rssi: -60,
+            connected: false,
+            mtu: 23,
+            baud_actual: 9615, // the actual rate of the initial 9600
+            loopback: false,
+            uart_tx: Vec::new(),
+            ch_notify: None,
+            fail_connects: 0,
+            fail_writes: 0,
+            panic_write: false,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn transport(&self) -> FakeTransport {
+        FakeTransport::new(std::slice::from_ref(self))
+    }
+
+    /// Starts or stops advertising. Stopping also breaks the current connection.
+    pub fn set_present(&self, present: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.present = present;
+        if !present {
+            Self::break_connection(&mut state);
+        }
+    }
+
+    /// Sets the signal strength reported in advertisements.
+    pub fn set_rssi(&self, rssi: i16) {
+        self.state.lock().unwrap().rssi = rssi;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    /// Breaks the connection from the peripheral side.
+    pub fn disconnect(&self) {
+        Self::break_connection(&mut self.state.lock().unwrap());
+    }
+
+    /// Sets the ATT MTU; notifications are split into `mtu - 3` bytes like the firmware does.
+    pub fn set_mtu(&self, mtu: usize) {
+        self.state.lock().unwrap().mtu = mtu.max(23);
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.state.lock().unwrap().baud_actual
+    }
+
+    /// Sends everything written to 0xB002 back through 0xB003.
+    pub fn set_loopback(&self, loopback: bool) {
+        self.state.lock().unwrap().loopback = loopback;
+    }
+
+    /// Makes the next `count` connection attempts fail.
+    pub fn fail_next_connects(&self, count: u32) {
+        self.state.lock().unwrap().fail_connects = count;
+    }
+
+    /// Makes the next `count` writes to 0xB002 fail.
+    pub fn fail_next_writes(&self, count: u32) {
+        self.state.lock().unwrap().fail_writes = count;
+    }
+
+    /// Makes the next write to 0xB002 panic, which happens in the background task.
+    pub fn panic_next_write(&self) {
+        self.state.lock().unwrap().panic_write = true;
+    }
+
+    /// Takes the data received by the emulated UART Tx.
+    pub fn take_uart_tx(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state.lock().unwrap().uart_tx)
+    }
+
+    /// Emulates data received from the UART Rx; it is sent as notifications of 0xB003
+    /// when notification is enabled, otherwise it's discarded like the firmware does.
+    pub fn push_uart_rx(&self, data: &[u8]) {
+        Self::notify_data(&mut self.state.lock().unwrap(), data);
+    }
+
+    fn notify_data(state: &mut FakeState, data: &[u8]) {
+        let Some(ch_notify) = state.ch_notify.as_ref() else {
+            return;
+        };
+        for chunk in data.chunks(state.mtu - 3) {
+            if ch_notify.send(chunk.to_vec()).is_err() {
+                // the central unsubscribed
+                state.ch_notify.take();
+                return;
+            }
+        }
+    }
+
+    fn break_connection(state: &mut FakeState) {
+        state.connected = false;
+        state.ch_notify.take();
+    }
+
+    // emulates `driver_uart_init()` of the firmware
+    fn uart_init(state: &mut FakeState, baud: u32) -> bool {
+        let Some(conf) = rtlbaud::auto_calc(baud) else {
+            return false;
+        };
+        state.baud_actual = conf.baud_actual;
+        true
+    }
+}
+
+struct FakeAdapterState {
+    powered: bool,
+    ch_events: Vec<tokio::sync::mpsc::UnboundedSender<bool>>,
+}
+
+/// Controller of the adapter of a [`FakeTransport`].
+#[derive(Clone)]
+pub struct FakeAdapter {
+    state: Arc<Mutex<FakeAdapterState>>,
+    periphs: Vec<FakePeripheral>,
+}
+
+impl FakeAdapter {
+    /// Powers the adapter on or off; powering off breaks the connections.
+    pub fn set_powered(&self, powered: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.powered == powered {
+            return;
+        }
+        state.powered = powered;
+        state.ch_events.retain(|ch| ch.send(powered).is_ok());
+        if !powered {
+            for periph in self.periphs.iter() {
+                FakePeripheral::break_connection(&mut periph.state.lock().unwrap());
+            }
+        }
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.state.lock().unwrap().powered
+    }
+}
+
+/// [`GattTransport`] reaching a set of [`FakePeripheral`]s.
+#[derive(Clone)]
+pub struct FakeTransport {
+    periphs: Vec<FakePeripheral>,
+    adapter: FakeAdapter,
+}
+
+impl FakeTransport {
+    pub fn new(periphs: &[FakePeripheral]) -> Self {
+        let state = FakeAdapterState {
+            powered: true,
+            ch_events: Vec::new(),
+        };
+        Self {
+            periphs: periphs.to_vec(),
+            adapter: FakeAdapter {
+                state: Arc::new(Mutex::new(state)),
+                periphs: periphs.to_vec(),
+            },
+        }
+    }
+
+    /// Returns the controller of the adapter, which is shared by the clones of the transport.
+    pub fn adapter(&self) -> FakeAdapter {
+        self.adapter.clone()
+    }
+
+    fn check_powered(&self) -> TransportResult<()> {
+        if !self.adapter.is_powered() {
+            return Err(TransportError::new("adapter powered off"));
+        }
+        Ok(())
+    }
+
+    // keeps "scanning" until the stream is dropped
+    fn advertisements<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> BoxStream<'a, Advertisement<FakeDevice>> {
+        let provides_serv = services.is_empty() || services.contains(&UUID_SERV);
+        Box::pin(async_stream::stream! {
+            if !provides_serv {
+                return;
+            }
+            loop {
+                // scanning stops when the adapter is powered off
+                if !self.adapter.is_powered() {
+                    return;
+                }
+                for periph in self.periphs.iter() {
+                    let adv = {
+                        let state = periph.state.lock().unwrap();
+                        state.present.then(|| Advertisement {
+                            device: FakeDevice {
+                                periph: periph.clone(),
+                            },
+                            local_name: Some(state.name.clone()),
+                            rssi: Some(state.rssi),
+                            is_connectable: Some(!state.connected),
+                        })
+                    };
+                    if let Some(adv) = adv {
+                        yield adv;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+    }
+}
+
+impl GattTransport for FakeTransport {
+    type Device = FakeDevice;
+
+    async fn wait_available(&self) -> TransportResult<()> {
+        let mut events = self.adapter_events().await?;
+        if self.adapter.is_powered() {
+            return Ok(());
+        }
+        while let Some(powered) = events.next().await {
+            if powered {
+                return Ok(());
+            }
+        }
+        Err(TransportError::new("adapter removed"))
+    }
+
+    async fn adapter_events(&self) -> TransportResult<BoxStream<'_, bool>> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        self.adapter.state.lock().unwrap().ch_events.push(tx);
+        Ok(Box::pin(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(rx),
+        ))
+    }
+
+    async fn discover_devices<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, TransportResult<FakeDevice>>> {
+        self.check_powered()?;
+        Ok(Box::pin(
+            self.advertisements(services).map(|adv| Ok(adv.device)),
+        ))
+    }
+
+    async fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, Advertisement<FakeDevice>>> {
+        self.check_powered()?;
+        Ok(self.advertisements(services))
+    }
+
+    async fn connect_device(&self, device: &FakeDevice) -> TransportResult<()> {
+        self.check_powered()?;
+        let mut state = device.periph.state.lock().unwrap();
+        if state.fail_connects > 0 {
+            state.fail_connects -= 1;
+            return Err(TransportError::new("connection failed"));
+        }
+        if !state.present {
+            return Err(TransportError::new("device not present"));
+        }
+        state.connected = true;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct FakeDevice {
+    periph: FakePeripheral,
+}
+
+impl GattDevice for FakeDevice {
+    type Characteristic = FakeCharacteristic;
+
+    fn id(&self) -> String {
+        self.periph.state.lock().unwrap().id.clone()
+    }
+
+    async fn name(&self) -> TransportResult<String> {
+        Ok(self.periph.state.lock().unwrap().name.clone())
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.periph.is_connected()
+    }
+
+    async fn service_characteristics(
+        &self,
+        service: Uuid,
+    ) -> TransportResult<Option<Vec<FakeCharacteristic>>> {
+        if !self.periph.is_connected() {
+            return Err(TransportError::new("not connected"));
+        }
+        if service != UUID_SERV {
+            return Ok(None);
+        }
+        Ok(Some(
+            [UUID_CHAR_BAUD, UUID_CHAR_WRITE, UUID_CHAR_READ]
+                .into_iter()
+                .map(|uuid| FakeCharacteristic {
+                    periph: self.periph.clone(),
+                    uuid,
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Clone)]
+pub struct FakeCharacteristic {
+    periph: FakePeripheral,
+    uuid: Uuid,
+}
+
+impl FakeCharacteristic {
+    fn write_value(&self, data: &[u8]) -> TransportResult<()> {
+        let mut state = self.periph.state.lock().unwrap();
+        if !state.connected {
+            return Err(TransportError::new("not connected"));
+        }
+        match self.uuid {
+            UUID_CHAR_BAUD => {
+                // the firmware ignores values of incorrect length or invalid baud rates
+                if let Ok(bytes_baud) = <[u8; 4]>::try_from(data) {
+                    FakePeripheral::uart_init(&mut state, u32::from_le_bytes(bytes_baud));
+                }
+                Ok(())
+            }
+            UUID_CHAR_WRITE => {
+                if std::mem::take(&mut state.panic_write) {
+                    drop(state); // keeps the state usable
+                    panic!("FakePeripheral: write panicked");
+                }
+                if state.fail_writes > 0 {
+                    state.fail_writes -= 1;
+                    return Err(TransportError::new("write failed"));
+                }
+                if data.len() > state.mtu - 3 {
+                    return Err(TransportError::new("invalid attribute value length"));
+                }
+                state.uart_tx.extend_from_slice(data);
+                if state.loopback {
+                    FakePeripheral::notify_data(&mut state, data);
+                }
+                Ok(())
+            }
+            _ => Err(TransportError::new("write not permitted")),
+        }
+    }
+}
+
+impl GattCharacteristic for FakeCharacteristic {
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    async fn read(&self) -> TransportResult<Vec<u8>> {
+        let state = self.periph.state.lock().unwrap();
+        if !state.connected {
+            return Err(TransportError::new("not connected"));
+        }
+        match self.uuid {
+            UUID_CHAR_BAUD => Ok(state.baud_actual.to_le_bytes().to_vec()),
+            _ => Err(TransportError::new("read not permitted")),
+        }
+    }
+
+    async fn write(&self, data: &[u8]) -> TransportResult<()> {
+        self.write_value(data)
+    }
+
+    async fn write_without_response(&self, data: &[u8]) -> TransportResult<()> {
+        self.write_value(data)
+    }
+
+    async fn max_write_len(&self) -> TransportResult<usize> {
+        Ok(self.periph.state.lock().unwrap().mtu - 3)
+    }
+
+    async fn notify(&self) -> TransportResult<BoxStream<'static, TransportResult<Vec<u8>>>> {
+        if self.uuid != UUID_CHAR_READ {
+            return Err(TransportError::new("notify not permitted"));
+        }
+        let mut state = self.periph.state.lock().unwrap();
+        if !state.connected {
+            return Err(TransportError::new("not connected"));
+        }
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        state.ch_notify.replace(tx);
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(data) = rx.recv().await {
+                yield Ok(data);
+            }
+        }))
+    }
+}
+
+/// Polls `cond` until it returns `true`, for up to 5 seconds; used by the tests.
+pub fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+    let t_end = std::time::Instant::now() + Duration::from_secs(5);
+    while !cond() {
+        if std::time::Instant::now() >= t_end {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+/// Receives events until one satisfying `matches`, for up to 5 seconds; returns
+/// all of them, the matching one last. Used by the tests.
+#[cfg(test)]
+pub(crate) fn wait_event(
+    events: &mut tokio::sync::broadcast::Receiver<crate::BleSerialEvent>,
+    matches: impl Fn(&crate::BleSerialEvent) -> bool,
+) -> Vec<crate::BleSerialEvent> {
+    let mut received = Vec::new();
+    let found = wait_until(|| {
+        while let Ok(evt) = events.try_recv() {
+            let found = matches(&evt);
+            received.push(evt);
+            if found {
+                return true;
+            }
+        }
+        false
+    });
+    assert!(found, "event not received; received: {received:?}");
+    received
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::{wait_until, FakePeripheral};
+    use crate::{BleSerialBuilder, BleSerialEvent, DeviceSelector, ReconnectPolicy};
+
+    fn builder() -> BleSerialBuilder {
+        BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .read_timeout(Duration::from_millis(2000))
+            .check_interval(Duration::from_millis(100))
+    }
+
+    #[test]
+    fn loopback() {
+        let fake = FakePeripheral::default();
+        fake.set_loopback(true);
+        let mut ble_ser = builder().build_with_transport(fake.transport()).unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        ble_ser.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        ble_ser.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn chunked_write() {
+        let fake = FakePeripheral::default();
+        fake.set_mtu(100);
+        let mut ble_ser = builder().build_with_transport(fake.transport()).unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        ble_ser.write_all(&data).unwrap();
+        ble_ser.flush().unwrap();
+        assert_eq!(fake.take_uart_tx(), data);
+        assert_eq!(ble_ser.stats().bytes_written, 5000);
+    }
+
+    #[test]
+    fn write_retries() {
+        let fake = FakePeripheral::default();
+        let mut ble_ser = builder()
+            .write_attempts(3)
+            .build_with_transport(fake.transport())
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        fake.fail_next_writes(2);
+        ble_ser.write_all(b"abc").unwrap();
+        ble_ser.flush().unwrap();
+        assert_eq!(fake.take_uart_tx(), b"abc");
+        let stats = ble_ser.stats();
+        assert_eq!(stats.write_retries, 2);
+        assert_eq!(stats.write_failures, 0);
+    }
+
+    #[test]
+    fn set_baud_rate() {
+        let fake = FakePeripheral::default();
+        let ble_ser = builder().build_with_transport(fake.transport()).unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        assert_eq!(ble_ser.set_baud_rate(115200).unwrap(), 115090);
+        assert_eq!(fake.baud_rate(), 115090);
+        assert_eq!(ble_ser.baud_rate(), Some(115090));
+    }
+
+    #[test]
+    fn reconnect_and_give_up() {
+        let fake = FakePeripheral::default();
+        let ble_ser = builder()
+            .reconnect_policy(ReconnectPolicy::Fixed {
+                delay: Duration::from_millis(50),
+                max_attempts: Some(2),
+            })
+            .build_with_transport(fake.transport())
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let mut events = ble_ser.subscribe().unwrap();
+        fake.fail_next_connects(u32::MAX);
+        fake.disconnect();
+
+        let mut attempts = Vec::new();
+        loop {
+            match events.blocking_recv().unwrap() {
+                BleSerialEvent::Reconnecting { attempt, .. } => attempts.push(attempt),
+                BleSerialEvent::GaveUp => break,
+                BleSerialEvent::Connect => panic!("connected unexpectedly"),
+                _ => (),
+            }
+        }
+        assert_eq!(attempts, [1, 2]);
+        // reading ends after giving up
+        assert_eq!((&ble_ser).read(&mut [0u8; 16]).unwrap(), 0);
+    }
+}
@@ -1,13 +1,13 @@
 // by wuwbobo2021 <wuwbobo@outlook.com>
 // for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
 
-const UUID_SERV: Uuid = bluetooth_uuid_from_u16(0xA00A);
+pub(crate) const UUID_SERV: Uuid = bluetooth_uuid_from_u16(0xA00A);
 
-const UUID_CHAR_BAUD: Uuid = bluetooth_uuid_from_u16(0xB001);
-const UUID_CHAR_READ: Uuid = bluetooth_uuid_from_u16(0xB003);
-const UUID_CHAR_WRITE: Uuid = bluetooth_uuid_from_u16(0xB002);
+pub(crate) const UUID_CHAR_BAUD: Uuid = bluetooth_uuid_from_u16(0xB001);
+pub(crate) const UUID_CHAR_READ: Uuid = bluetooth_uuid_from_u16(0xB003);
+pub(crate) const UUID_CHAR_WRITE: Uuid = bluetooth_uuid_from_u16(0xB002);
 
-const UUID_DESC_CLIENT_CHAR_CONF: Uuid = bluetooth_uuid_from_u16(0x2902);
+pub(crate) const UUID_DESC_CLIENT_CHAR_CONF: Uuid = bluetooth_uuid_from_u16(0x2902);
 
 #[cfg(feature = "debug")]
 macro_rules! debug {
@@ -19,488 +19,393 @@ macro_rules! debug {
     ($($arg:tt)+) => {};
 }
 
+mod async_serial;
+#[cfg(target_os = "linux")]
+mod bluer_transport;
+mod builder;
+mod error;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+mod link;
+mod link_handle;
+mod manager;
+mod reconnect;
+pub mod rtlbaud;
+mod scan;
+mod selector;
+mod serial_port;
+mod split;
+mod stats;
+pub mod transport;
+
+pub use async_serial::AsyncBleSerial;
+pub use builder::BleSerialBuilder;
+pub use error::BleSerialError;
+pub use link::{Health, ReadOverflow, WriteMode};
+pub use link_handle::LinkHandle;
+pub use manager::BleSerialManager;
+pub use reconnect::ReconnectPolicy;
+pub use scan::{scan, scan_adapter, scan_stream, scan_with_transport, DiscoveredBridge};
+pub use selector::{AdapterSelector, DeviceSelector};
+pub use split::{BleSerialReader, BleSerialWriter};
+pub use stats::LinkStats;
+
 use std::{
-    collections::VecDeque,
     io::{self, Read, Write},
-    pin::Pin,
+    ops::Deref,
     sync::{Arc, Mutex},
     thread,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant},
 };
 
-use bluest::{btuuid::bluetooth_uuid_from_u16, Characteristic};
+use bluest::btuuid::bluetooth_uuid_from_u16;
 
-use futures::StreamExt;
+use futures::Future;
 use uuid::Uuid;
 
+use link::{BleHdlMsg, BleSerialRes};
+use transport::GattTransport;
+
+#[derive(Debug, Clone)]
 pub enum BleSerialEvent {
     Connect,
     Disconnect,
     Receive(Vec<u8>),
     WriteFailed(Vec<u8>),
-}
-
-enum BleHdlMsg {
-    ReqSetBaud(u32),
-    ReqWrite(Vec<u8>),
-    ReqDrop,
-    ReadNotify(Vec<u8>),
-    Timer,
-}
-type PinnedMsgStream = Pin<Box<dyn tokio_stream::Stream<Item = BleHdlMsg> + Send>>;
-
-struct BleSerialRes {
-    rt: Option<tokio::runtime::Runtime>,
-    dev_addr: String, //cannot be changed
-    dev_name: Option<String>,
-    baud_rate: u32,
-    buf_read: VecDeque<u8>,
-    ch_req: Option<tokio::sync::mpsc::UnboundedSender<BleHdlMsg>>,
-    on_event: Arc<Box<dyn Fn(BleSerialEvent) + 'static + Send + Sync>>,
+    Error(BleSerialError),
+    /// Going to reconnect after `next_in`, according to the [`ReconnectPolicy`].
+    Reconnecting {
+        attempt: u32,
+        next_in: Duration,
+    },
+    /// No more reconnect attempts will be made.
+    GaveUp,
+    /// The adapter is powered off or removed; the connection resumes when it's back.
+    /// It follows `Error(AdapterPoweredOff)` if the adapter is powered off.
+    AdapterUnavailable,
+    AdapterAvailable,
 }
 
 pub struct BleSerial {
-    res: Arc<Mutex<BleSerialRes>>,
+    rt: Option<Arc<tokio::runtime::Runtime>>, // shared by the handles of `BleSerialManager`
+    task: tokio::task::JoinHandle<()>,
+    link: LinkHandle,
     read_timeout: Duration,
+    nonblocking_write: bool,
 }
 
 impl BleSerial {
-    pub fn build(device_bt_addr: &str, read_timeout: Duration) -> Result<Self, &'static str> {
-        // the default Runtime::new() will create a thread for each CPU core (too many threads)
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(2)
-            .enable_all()
+    /// Builds `BleSerial` connecting to the device chosen by `device`, which can be
+    /// a [`DeviceSelector`] or the device id string.
+    /// See [`BleSerialBuilder`] for other options.
+    pub fn build(
+        device: impl Into<DeviceSelector>,
+        read_timeout: Duration,
+    ) -> Result<Self, BleSerialError> {
+        BleSerialBuilder::new(device)
+            .read_timeout(read_timeout)
             .build()
-            .map_err(|_| "can't create async runtime required by the bluetooth library")?;
-
-        let res = BleSerialRes {
-            rt: Some(rt),
-            dev_addr: device_bt_addr.to_string(),
-            dev_name: None,
-            baud_rate: 9600_u32,
-            buf_read: VecDeque::<u8>::new(),
-            ch_req: None,
-            on_event: Arc::new(Box::new(|_| {})),
-        };
-        let arc_res = Arc::new(Mutex::new(res));
-        let arc_res_2 = arc_res.clone();
-
-        arc_res
-            .lock()
-            .map_err(|_| "unexpected error")?
-            .rt
-            .as_ref()
-            .unwrap()
-            .spawn(Self::ble_loop(arc_res_2));
-        Ok(Self {
-            res: arc_res,
-            read_timeout,
-        })
     }
 
-    pub fn is_connected(&self) -> bool {
-        self.device_name().is_some()
+    /// Builds `BleSerial` working with the given transport instead of the default
+    /// bluetooth adapter, e.g. the fake peripheral of the `fake` feature.
+    pub fn build_with_transport<T: GattTransport>(
+        transport: T,
+        device: impl Into<DeviceSelector>,
+        read_timeout: Duration,
+    ) -> Result<Self, BleSerialError> {
+        BleSerialBuilder::new(device)
+            .read_timeout(read_timeout)
+            .build_with_transport(transport)
     }
 
-    pub fn device_name(&self) -> Option<String> {
-        if let Ok(lck_res) = self.res.lock() {
-            lck_res.dev_name.clone()
-        } else {
-            None
-        }
+    pub(crate) fn build_inner<F: Future<Output = ()> + Send + 'static>(
+        res: BleSerialRes,
+        worker_threads: usize,
+        read_timeout: Duration,
+        f_loop: impl FnOnce(Arc<Mutex<BleSerialRes>>) -> F,
+    ) -> Result<Self, BleSerialError> {
+        // the default Runtime::new() will create a thread for each CPU core (too many threads)
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads)
+            .enable_all()
+            .build()
+            .map_err(|_| BleSerialError::Runtime)?;
+        Ok(Self::spawn_in(Arc::new(rt), res, read_timeout, f_loop))
     }
 
-    pub fn baud_rate(&self) -> Option<u32> {
-        if self.is_connected() {
-            Some(self.res.lock().unwrap().baud_rate)
-        } else {
-            None
+    /// Spawns the background task on an existing runtime.
+    pub(crate) fn spawn_in<F: Future<Output = ()> + Send + 'static>(
+        rt: Arc<tokio::runtime::Runtime>,
+        res: BleSerialRes,
+        read_timeout: Duration,
+        f_loop: impl FnOnce(Arc<Mutex<BleSerialRes>>) -> F,
+    ) -> Self {
+        let arc_res = Arc::new(Mutex::new(res));
+        let task = rt.spawn(f_loop(arc_res.clone()));
+        Self {
+            rt: Some(rt),
+            task,
+            link: LinkHandle { res: arc_res },
+            read_timeout,
+            nonblocking_write: false,
         }
     }
 
-    pub fn set_baud_rate(&self, baud: u32) -> Result<u32, Option<u32>> {
-        if baud == 0 {
-            return Err(self.baud_rate());
-        }
-
-        let lck_res = self.res.lock().map_err(|_| None)?;
-        if lck_res.ch_req.is_none() || lck_res.dev_name.is_none() {
-            return Err(None);
+    pub fn set_baud_rate(&self, baud: u32) -> Result<u32, BleSerialError> {
+        let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        // impossible baud rates are rejected without asking the device
+        let Some(expected) = link::expected_baud(baud) else {
+            return Err(lck_res.baud_rejected(baud));
+        };
+        if !lck_res.send_req(BleHdlMsg::ReqSetBaud(baud)) {
+            return Err(BleSerialError::Disconnected);
         }
-        lck_res
-            .ch_req
-            .as_ref()
-            .unwrap()
-            .send(BleHdlMsg::ReqSetBaud(baud))
-            .map_err(|_| lck_res.baud_rate)?;
+        let t_end = Instant::now() + lck_res.config.baud_set_timeout;
         drop(lck_res);
 
-        for _ in 0..10 {
-            thread::sleep(Duration::from_millis(1000));
-            let cur_baud = self.res.lock().map_err(|_| None)?.baud_rate;
-            if Self::baud_acceptable(cur_baud, baud) {
+        while Instant::now() < t_end {
+            thread::sleep(Duration::from_millis(100));
+            let cur_baud = self
+                .res
+                .lock()
+                .map_err(|_| BleSerialError::Poisoned)?
+                .baud_rate;
+            if cur_baud == expected {
                 return Ok(cur_baud);
             }
         }
-        Err(self.baud_rate())
-    }
-
-    pub fn drain_read_buf(&self) -> Vec<u8> {
-        if let Ok(mut lck_res) = self.res.lock() {
-            lck_res.buf_read.drain(..).collect::<Vec<u8>>()
-        } else {
-            Vec::new()
-        }
+        let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        Err(lck_res.baud_rejected(baud))
     }
 
-    pub fn on_event(
-        &self,
-        f: impl Fn(BleSerialEvent) + 'static + Send + Sync,
-    ) -> Result<(), &'static str> {
-        let mut lck_res = self
-            .res
-            .lock()
-            .map_err(|_| "error that shouldn't happen: unable to set event handler")?;
-        lck_res.on_event = Arc::new(Box::new(f));
-        Ok(())
+    /// If set, `write()` returns `WouldBlock` instead of waiting when the queue is full.
+    pub fn set_nonblocking_write(&mut self, nonblocking: bool) {
+        self.nonblocking_write = nonblocking;
     }
 
-    async fn ble_loop(res: Arc<Mutex<BleSerialRes>>) {
-        debug!("ble_loop(): entered.");
-
-        let dev_addr = res.lock().as_ref().unwrap().dev_addr.clone();
-
-        // TODO: deal with disabled bluetooth adapter
-        let Some(adapter) = bluest::Adapter::default().await else {
-            debug!("ble_loop(): bluetooth adapter not found.");
-            return;
-        };
-        adapter.wait_available().await.unwrap();
-
-        loop {
-            // create `req` (external call) message channel as soon as possible
-            let (tx_req, mut rx_req) = tokio::sync::mpsc::unbounded_channel::<BleHdlMsg>();
-            res.lock().as_mut().unwrap().ch_req.replace(tx_req);
-            let mut msg_map = tokio_stream::StreamMap::new();
-            msg_map.insert(
-                "req",
-                tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
-                    while let Some(item) = rx_req.recv().await {
-                        yield item;
-                    }
-                }) as PinnedMsgStream),
-            );
-
-            // indicate disconnection
-            let prev_name = res.lock().unwrap().dev_name.take();
-            if prev_name.is_some() {
-                debug!("ble_loop(): disconnected.");
-                Self::raise_event(&res, BleSerialEvent::Disconnect);
-            }
-
-            // avoid useless retrying if the bluetooth device is not present
-            tokio::time::sleep(Duration::from_millis(1500)).await;
-
-            let filter = [UUID_SERV];
-            let Ok(mut discoverer) = adapter.discover_devices(&filter).await else {
-                debug!("ble_loop(): discover_devices failed.");
-                continue;
-            };
-            debug!("ble_loop(): started discovering.");
-
-            // check the device's MAC address
-            let mut device = None;
-            'outer: for _ in 0..10 {
-                tokio::time::sleep(Duration::from_millis(1000)).await;
-                while let Some(Ok(dev)) = discoverer.next().await {
-                    let id = dev.id().to_string();
-                    println!("ble_loop(): found {}.", &id);
-                    if id.to_lowercase().contains(&dev_addr.to_lowercase()) {
-                        device = Some(dev);
-                        break 'outer;
-                    }
-                }
-            }
-            let Some(device) = device else {
-                debug!("ble_loop(): target device not found.");
-                continue;
-            };
-            drop(discoverer);
-
-            // connect and get the characteristics
-            if adapter.connect_device(&device).await.is_err() {
-                debug!("ble_loop(): failed to connect.");
-                continue;
-            }
-            if device.discover_services().await.is_err() {
-                debug!("ble_loop(): failed to discover services (unexpected).");
-                continue;
-            }
-            let Ok(services) = device.services().await else {
-                debug!("ble_loop(): cannot get device services (unexpected).");
-                continue;
-            };
-            let Some(service) = services.iter().find(|serv| serv.uuid() == UUID_SERV) else {
-                debug!("ble_loop(): cannot find the correct service (unexpected).");
-                continue;
-            };
-            let Ok(chars) = service.characteristics().await else {
-                debug!("ble_loop(): cannot get service characteristics (unexpected).");
-                continue;
-            };
-            let (char_baud, char_read, char_write) = {
-                let (mut ch_baud, mut ch_read, mut ch_write) = (None, None, None);
-                for ch in chars {
-                    match ch.uuid() {
-                        UUID_CHAR_BAUD => ch_baud.replace(ch),
-                        UUID_CHAR_READ => ch_read.replace(ch),
-                        UUID_CHAR_WRITE => ch_write.replace(ch),
-                        _ => None,
-                    };
-                }
-                if ch_baud.is_none() || ch_read.is_none() || ch_write.is_none() {
-                    debug!("ble_loop(): incorrect characteristics.");
-                    continue;
-                }
-                (ch_baud.unwrap(), ch_read.unwrap(), ch_write.unwrap())
-            };
-
-            if let Some(baud) = Self::read_baud(&char_baud).await {
-                res.lock().unwrap().baud_rate = baud;
+    /// Fills `buf` completely, or returns `TimedOut` after `timeout`, or `UnexpectedEof` if
+    /// the background task has ended. In case of failure, received bytes are kept in the
+    /// read buffer; `InvalidInput` is returned if `buf` is larger than its capacity.
+    pub fn read_exact_timeout(&self, buf: &mut [u8], timeout: Duration) -> io::Result<()> {
+        self.wait_read(Instant::now() + timeout, |res| {
+            if buf.len() > res.read_capacity {
+                Some(Err(io::ErrorKind::InvalidInput.into()))
+            } else if res.buf_read.len() >= buf.len() {
+                res.take_read(buf);
+                Some(Ok(()))
             } else {
-                debug!("ble_loop(): failed to check baud rate.");
-                continue;
+                res.task_ended()
+                    .then(|| Err(io::ErrorKind::UnexpectedEof.into()))
             }
+        })?
+        .unwrap_or(Err(io::ErrorKind::TimedOut.into()))
+    }
 
-            // enable read notification
-            let Some(desc_char_conf) = char_read.descriptors().await.ok().and_then(|descs| {
-                descs
-                    .into_iter()
-                    .find(|d| d.uuid() == UUID_DESC_CLIENT_CHAR_CONF)
-            }) else {
-                debug!("ble_loop(): failed to get conf desc of char_read.");
-                continue;
+    /// Reads until `delim` is received, returning the bytes including `delim`. In case
+    /// of timeout or `UnexpectedEof`, received bytes are kept in the read buffer.
+    pub fn read_until(&self, delim: u8, timeout: Duration) -> io::Result<Vec<u8>> {
+        self.wait_read(Instant::now() + timeout, |res| {
+            let Some(pos) = res.buf_read.iter().position(|&b| b == delim) else {
+                return res
+                    .task_ended()
+                    .then(|| Err(io::ErrorKind::UnexpectedEof.into()));
             };
-            if desc_char_conf.write(&[0x01, 0x00]).await.is_err() {
-                // enable notification
-                debug!("ble_loop(): failed to write conf desc of char_read.");
-            }
+            let mut data = vec![0; pos + 1];
+            res.take_read(&mut data);
+            Some(Ok(data))
+        })?
+        .unwrap_or(Err(io::ErrorKind::TimedOut.into()))
+    }
 
-            // create UART read notification stream
-
-            msg_map.insert(
-                "read",
-                tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
-                    use futures::stream::StreamExt;
-                    // XXX: remove this `unwrap()`.
-                    let mut stream_notify_read = char_read.notify().await.unwrap();
-                    while let Some(Ok(item)) = stream_notify_read.next().await {
-                        yield BleHdlMsg::ReadNotify(item);
-                    }
-                }) as PinnedMsgStream),
-            );
-            msg_map.insert(
-                "timer",
-                tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
-                    loop {
-                        tokio::time::sleep(Duration::from_millis(2000)).await;
-                        yield BleHdlMsg::Timer;
-                    }
-                }) as PinnedMsgStream),
-            );
-
-            // get device name and indicate for connection
-            let dev_name = device.name_async().await.unwrap_or("unknown".to_string());
-            res.lock().unwrap().dev_name.replace(dev_name);
-            Self::raise_event(&res, BleSerialEvent::Connect);
-
-            // handle messages
-            while let Some((key, msg)) = msg_map.next().await {
-                if msg.is_none() {
-                    debug!("ble_loop(): stream {key} ends, breaking.");
-                    break; // the BLE connection is broken, or the req stream is broken
-                }
-                let msg = msg.unwrap();
-                if key == "read" {
-                    // read notification
-                    if let BleHdlMsg::ReadNotify(data) = msg {
-                        // With `VecDeque`, all data should be written into it
-                        let _ = res.lock().as_mut().unwrap().buf_read.write(&data).unwrap();
-                        Self::raise_event(&res, BleSerialEvent::Receive(data));
-                    }
-                    continue;
-                } else if key == "timer" {
-                    // connection checker
-                    // TODO: check for disabled bluetooth (adapter is probably still available in bluest!)
-                    if !device.is_connected().await {
-                        debug!("ble_loop(): disconnected, breaking.");
-                        break;
-                    }
-                    continue;
-                }
-                match msg {
-                    // request message
-                    BleHdlMsg::ReqSetBaud(baud) => {
-                        for _ in 0..3 {
-                            if char_baud
-                                .write_without_response(&baud.to_le_bytes())
-                                .await
-                                .is_ok()
-                            {
-                                break;
-                            }
-                        }
-                        let mut suc = false;
-                        for _ in 0..10 {
-                            let cur_baud = Self::read_baud(&char_baud).await.unwrap_or(0);
-                            if Self::baud_acceptable(cur_baud, baud) {
-                                debug!("ble_loop(): baudrate set.");
-                                res.lock().unwrap().baud_rate = cur_baud;
-                                suc = true;
-                                break;
-                            } else {
-                                tokio::time::sleep(Duration::from_millis(400)).await;
-                            }
-                        }
-                        if !suc {
-                            debug!("ble_loop(): failed to set baud rate.");
-                        }
-                    }
-                    BleHdlMsg::ReqWrite(data) => {
-                        // TODO: handle larger data block to be sent
-                        let mut suc = false;
-                        for _ in 0..3 {
-                            if char_write.write(&data).await.is_ok() {
-                                suc = true;
-                                break;
-                            }
-                        }
-                        if !suc {
-                            debug!("ble_loop(): write failed.");
-                            Self::raise_event(&res, BleSerialEvent::WriteFailed(data));
-                        }
-                    }
-                    BleHdlMsg::ReqDrop => {
-                        debug!("ble_loop(): ready to be dropped, return.");
-                        return;
-                    }
-                    _ => (),
-                }
-            }
+    // queues the data regardless of the write queue limit
+    pub(crate) fn queue_broadcast(&self, data: &[u8]) -> Result<(), BleSerialError> {
+        if data.is_empty() {
+            return Ok(());
         }
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        if !lck_res.queue_write(data.to_vec()) {
+            return Err(BleSerialError::Disconnected);
+        }
+        Ok(())
     }
 
-    async fn read_baud(char_baud: &Characteristic) -> Option<u32> {
-        for _ in 0..3 {
-            if let Ok(bytes_baud) = char_baud.read().await {
-                if bytes_baud.len() < 4 {
-                    continue;
-                }
-                let mut arr_baud = [0u8; 4];
-                arr_baud.copy_from_slice(&bytes_baud[..4]);
-                return Some(u32::from_le_bytes(arr_baud));
-            } else {
-                continue;
-            }
+    // waits until the queued bytes are sent, see `flush()`
+    pub(crate) fn wait_flushed(&self) -> Result<(), BleSerialError> {
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        let cond = lck_res.write_cond.clone();
+        while !lck_res.check_flushed()? {
+            lck_res = cond.wait(lck_res).map_err(|_| BleSerialError::Poisoned)?;
         }
-        None
+        Ok(())
     }
 
-    fn raise_event(res: &Arc<Mutex<BleSerialRes>>, evt: BleSerialEvent) {
-        let mut lck_res = res.lock().unwrap();
-        let on_event = lck_res.on_event.clone();
-        let rt = lck_res.rt.take();
-        if rt.is_none() {
-            return;
+    // calls `f` each time data is received, until it returns `Some` or `t_end` is reached.
+    fn wait_read<T>(
+        &self,
+        t_end: Instant,
+        mut f: impl FnMut(&mut BleSerialRes) -> Option<T>,
+    ) -> Result<Option<T>, BleSerialError> {
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        let cond = lck_res.read_cond.clone();
+        loop {
+            if let Some(val) = f(&mut lck_res) {
+                return Ok(Some(val));
+            }
+            let now = Instant::now();
+            if now >= t_end {
+                return Ok(None);
+            }
+            lck_res = cond
+                .wait_timeout(lck_res, t_end - now)
+                .map_err(|_| BleSerialError::Poisoned)?
+                .0;
         }
-        drop(lck_res);
+    }
+}
+
+impl Deref for BleSerial {
+    type Target = LinkHandle;
 
-        rt.as_ref().unwrap().spawn_blocking(move || on_event(evt));
-        res.lock().unwrap().rt.replace(rt.unwrap());
+    fn deref(&self) -> &LinkHandle {
+        &self.link
     }
+}
 
-    fn baud_acceptable(baud: u32, baud_expected: u32) -> bool {
-        if baud == 0 || baud_expected == 0 {
-            return false;
+impl Read for &BleSerial {
+    /// Returns as soon as some data is available, or `TimedOut` after `read_timeout`.
+    /// Returns 0 (end of file) once the buffer is empty and the background task has ended.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
         }
-        let t_baud = 1. / (baud as f64);
-        let t_baud_exp = 1. / (baud_expected as f64);
-        f64::abs(t_baud - t_baud_exp) / t_baud_exp <= 0.05
+        let t_end = Instant::now() + self.read_timeout;
+        self.wait_read(t_end, |res| {
+            let cnt = res.take_read(buf);
+            (cnt > 0 || res.read_ended()).then_some(cnt)
+        })?
+        .ok_or(io::Error::from(io::ErrorKind::TimedOut))
     }
 }
 
-impl Read for BleSerial {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+impl Write for &BleSerial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
 
-        let t_timeout = SystemTime::now() + self.read_timeout;
-        let mut cnt_read = 0;
-        while cnt_read < buf.len() {
-            let mut lck_res = self
-                .res
-                .lock()
-                .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
-            if let Ok(cnt) = lck_res.buf_read.read(&mut buf[cnt_read..]) {
-                cnt_read += cnt;
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        let cond = lck_res.write_cond.clone();
+        loop {
+            if lck_res.dev_name.is_none() {
+                return Err(BleSerialError::Disconnected.into());
             }
-            drop(lck_res);
-            if SystemTime::now() < t_timeout {
-                thread::sleep(Duration::from_millis(30));
-            } else {
-                break;
+            let room = lck_res.write_room();
+            if room > 0 {
+                let cnt = room.min(buf.len());
+                if !lck_res.queue_write(buf[..cnt].to_vec()) {
+                    return Err(BleSerialError::Disconnected.into());
+                }
+                return Ok(cnt);
             }
+            if self.nonblocking_write {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            lck_res = cond.wait(lck_res).map_err(|_| BleSerialError::Poisoned)?;
         }
+    }
 
-        if cnt_read == 0 {
-            Err(io::Error::from(io::ErrorKind::TimedOut))
-        } else {
-            Ok(cnt_read)
-        }
+    /// Waits until all queued bytes are sent to the device. Fails with the bytes
+    /// that couldn't be sent since the last call, including bytes dropped on disconnection.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.wait_flushed()?)
+    }
+}
+
+impl Read for BleSerial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
     }
 }
 
 impl Write for BleSerial {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.is_empty() {
-            return Ok(0);
-        }
-
-        let lck_res = self
-            .res
-            .lock()
-            .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
-
-        if lck_res.ch_req.is_none() || lck_res.dev_name.is_none() {
-            return Err(io::Error::from(io::ErrorKind::NotConnected));
-        }
-        lck_res
-            .ch_req
-            .as_ref()
-            .unwrap()
-            .send(BleHdlMsg::ReqWrite(buf.to_vec()))
-            .map_err(io::Error::other)?;
-        Ok(buf.len())
+        (&*self).write(buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        (&*self).flush()
     }
 }
 
 impl Drop for BleSerial {
     fn drop(&mut self) {
         debug!("BleSerial::drop(): entered.");
-        if let Ok(mut lck_res) = self.res.lock() {
-            if let Some(ch_req) = lck_res.ch_req.take() {
-                if ch_req.send(BleHdlMsg::ReqDrop).is_ok() {
-                    let rt = lck_res.rt.take().unwrap();
-                    drop(lck_res);
-                    rt.shutdown_timeout(Duration::from_millis(2000));
-                    debug!("BleSerial::drop(): shutdown_timeout() called.");
-                }
-            }
+        // the runtime may be shared, so the task must be stopped even if it's
+        // still discovering the device
+        self.task.abort();
+        // the runtime of `BleSerialManager` is shut down with its last handle
+        if let Some(rt) = self.rt.take().and_then(Arc::into_inner) {
+            rt.shutdown_timeout(Duration::from_millis(2000));
+            debug!("BleSerial::drop(): shutdown_timeout() called.");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use crate::{
+        fake::{wait_until, FakePeripheral},
+        BleSerial, DeviceSelector,
+    };
+
+    fn open_loopback(fake: &FakePeripheral) -> BleSerial {
+        fake.set_loopback(true);
+        let ble_ser = BleSerial::build_with_transport(
+            fake.transport(),
+            DeviceSelector::FirstFound,
+            Duration::from_millis(500),
+        )
+        .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        ble_ser
+    }
+
+    #[test]
+    fn read_until() {
+        let fake = FakePeripheral::default();
+        let mut ble_ser = open_loopback(&fake);
+        ble_ser.write_all(b"OK\r\nRE").unwrap();
+        let timeout = Duration::from_millis(1000);
+        assert_eq!(ble_ser.read_until(b'\n', timeout).unwrap(), b"OK\r\n");
+
+        let e = ble_ser
+            .read_until(b'\n', Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        // the partial line is kept
+        ble_ser.write_all(b"ADY\n").unwrap();
+        assert_eq!(ble_ser.read_until(b'\n', timeout).unwrap(), b"READY\n");
+    }
+
+    #[test]
+    fn read_exact_timeout() {
+        let fake = FakePeripheral::default();
+        let mut ble_ser = open_loopback(&fake);
+        let mut buf = [0u8; 5];
+        ble_ser.write_all(b"abc").unwrap();
+        let e = ble_ser
+            .read_exact_timeout(&mut buf, Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        // the partial data is kept
+        ble_ser.write_all(b"defg").unwrap();
+        ble_ser
+            .read_exact_timeout(&mut buf, Duration::from_millis(1000))
+            .unwrap();
+        assert_eq!(&buf, b"abcde");
+        assert!(wait_until(|| ble_ser.drain_read_buf() == b"fg"));
+    }
+}
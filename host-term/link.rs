@@ -0,0 +1,1172 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Background BLE task shared by [`crate::BleSerial`] and [`crate::AsyncBleSerial`].
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::Waker,
+    thread,
+    time::Duration,
+};
+
+use futures::{FutureExt, StreamExt};
+use tokio::time::Instant;
+
+use crate::{
+    rtlbaud,
+    transport::{
+        with_system_adapter, BoxStream, GattCharacteristic, GattDevice, GattTransport,
+        TransportError, TransportResult, WithTransport,
+    },
+    AdapterSelector, BleSerialError, BleSerialEvent, DeviceSelector, LinkStats, ReconnectPolicy,
+    UUID_CHAR_BAUD, UUID_CHAR_READ, UUID_CHAR_WRITE, UUID_SERV,
+};
+
+// events waiting for the handler; `ble_loop()` waits when it's full
+const EVENT_QUEUE_LEN: usize = 256;
+// events kept for each subscriber; a slower subscriber misses the older ones
+const SUBSCRIBER_QUEUE_LEN: usize = 256;
+
+pub(crate) type EventHandler = Arc<Box<dyn Fn(BleSerialEvent) + 'static + Send + Sync>>;
+
+pub(crate) enum BleHdlMsg {
+    ReqSetBaud(u32),
+    ReqWrite(Vec<u8>),
+    ReqResumeRead,
+    ReadNotify(Vec<u8>),
+    ReadFailed(TransportError),
+    AdapterPowered(bool),
+    Timer,
+}
+type PinnedMsgStream<'a> = Pin<Box<dyn tokio_stream::Stream<Item = BleHdlMsg> + Send + 'a>>;
+
+/// GATT write procedure used for characteristic 0xB002.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteMode {
+    /// Each chunk is acknowledged by the device.
+    #[default]
+    WithResponse,
+    /// Faster, but the device may drop data if its UART Tx can't keep up.
+    WithoutResponse,
+}
+
+/// What to do with received data when the read buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadOverflow {
+    /// Discard the oldest bytes in the buffer.
+    #[default]
+    DropOldest,
+    /// Discard the received bytes that don't fit in.
+    DropNewest,
+    /// Disable notification until there is room in the buffer. Nothing is dropped
+    /// on the host, but the bridge discards UART data received meanwhile.
+    StopNotify,
+}
+
+/// Timing constants of `ble_loop()`, set by [`crate::BleSerialBuilder`].
+#[derive(Debug, Clone)]
+pub(crate) struct LinkConfig {
+    pub reconnect: ReconnectPolicy,
+    pub discovery_timeout: Duration,
+    pub check_interval: Duration,
+    pub write_attempts: u32,
+    pub baud_verify_attempts: u32,
+    pub baud_verify_interval: Duration,
+    pub baud_set_timeout: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: ReconnectPolicy::default(),
+            discovery_timeout: Duration::from_millis(10 * 1000),
+            check_interval: Duration::from_millis(2000),
+            write_attempts: 3,
+            baud_verify_attempts: 10,
+            baud_verify_interval: Duration::from_millis(400),
+            baud_set_timeout: Duration::from_millis(10 * 1000),
+        }
+    }
+}
+
+/// State of the background task, reported by `health()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The background task is running and the device is connected.
+    Connected,
+    /// The background task is running, trying to connect.
+    Connecting,
+    /// The background task has ended after `BleSerialEvent::GaveUp`.
+    Stopped,
+    /// The background task has panicked; this is a bug.
+    Panicked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TaskState {
+    Running,
+    Stopped,
+    Panicked,
+}
+
+pub(crate) struct BleSerialRes {
+    pub selector: DeviceSelector, //cannot be changed
+    pub config: LinkConfig,       //cannot be changed
+    pub dev_name: Option<String>,
+    pub baud_rate: u32,
+    pub buf_read: VecDeque<u8>,
+    pub read_capacity: usize,
+    pub read_overflow: ReadOverflow,
+    pub read_paused: bool, // notification is disabled by `ReadOverflow::StopNotify`
+    pub ch_req: Option<tokio::sync::mpsc::UnboundedSender<BleHdlMsg>>,
+    pub on_event: EventHandler,
+    pub ch_event: Option<tokio::sync::mpsc::Sender<(EventHandler, BleSerialEvent)>>, // to the dispatcher thread
+    pub subscribers: tokio::sync::broadcast::Sender<BleSerialEvent>,
+    pub read_waker: Option<Waker>, // set by the pending `AsyncRead::poll_read()`
+    pub read_cond: Arc<Condvar>,   // waited by the blocking `BleSerial::read()`
+    pub last_error: Option<BleSerialError>,
+    pub write_mode: WriteMode,
+    pub write_queue_limit: usize,
+    pub write_pending: usize,       // bytes queued but not yet sent
+    pub write_waker: Option<Waker>, // set by the pending `poll_write()` or `poll_flush()`
+    pub write_cond: Arc<Condvar>,   // waited by the blocking `write()` and `flush()`
+    pub write_undelivered: Vec<u8>, // taken by `flush()`
+    pub stats: LinkStats,
+    pub connected_once: bool,
+    pub connected_since: Option<std::time::Instant>,
+    pub task_state: TaskState,
+}
+
+impl BleSerialRes {
+    pub fn new(selector: DeviceSelector, config: LinkConfig) -> Self {
+        Self {
+            selector,
+            config,
+            dev_name: None,
+            baud_rate: 9600_u32,
+            buf_read: VecDeque::<u8>::new(),
+            read_capacity: 64 * 1024,
+            read_overflow: ReadOverflow::default(),
+            read_paused: false,
+            ch_req: None,
+            on_event: Arc::new(Box::new(|_| {})),
+            ch_event: None,
+            subscribers: tokio::sync::broadcast::channel(SUBSCRIBER_QUEUE_LEN).0,
+            read_waker: None,
+            read_cond: Arc::new(Condvar::new()),
+            last_error: None,
+            write_mode: WriteMode::default(),
+            write_queue_limit: 4096,
+            write_pending: 0,
+            write_waker: None,
+            write_cond: Arc::new(Condvar::new()),
+            write_undelivered: Vec::new(),
+            stats: LinkStats::default(),
+            connected_once: false,
+            connected_since: None,
+            task_state: TaskState::Running,
+        }
+    }
+
+    /// Adds a receiver of the events raised from now on.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<BleSerialEvent> {
+        self.subscribers.subscribe()
+    }
+
+    // starts the dispatcher thread on the first event; it calls the handlers in order,
+    // and exits when `BleSerialRes` is dropped.
+    fn event_sender(&mut self) -> tokio::sync::mpsc::Sender<(EventHandler, BleSerialEvent)> {
+        self.ch_event
+            .get_or_insert_with(|| {
+                let (tx, mut rx) =
+                    tokio::sync::mpsc::channel::<(EventHandler, BleSerialEvent)>(EVENT_QUEUE_LEN);
+                thread::spawn(move || {
+                    while let Some((on_event, evt)) = rx.blocking_recv() {
+                        // a panicking handler shouldn't stop the delivery of later events
+                        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| on_event(evt)));
+                    }
+                });
+                tx
+            })
+            .clone()
+    }
+
+    /// Error for a failed attempt to set the baud rate.
+    pub fn baud_rejected(&self, requested: u32) -> BleSerialError {
+        if self.dev_name.is_none() {
+            return BleSerialError::Disconnected;
+        }
+        BleSerialError::BaudRejected {
+            requested,
+            actual: self.baud_rate,
+        }
+    }
+
+    // called by `ble_loop()` for received data; returns the bytes to be held
+    // until notification is resumed with `ReadOverflow::StopNotify`.
+    fn push_read<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        let room = self.read_capacity.saturating_sub(self.buf_read.len());
+        let (fit, rest) = data.split_at(room.min(data.len()));
+        self.buf_read.extend(fit);
+        let held = match self.read_overflow {
+            ReadOverflow::DropOldest => {
+                let cnt_skip = rest.len().saturating_sub(self.read_capacity);
+                let rest = &rest[cnt_skip..];
+                let cnt_drain = rest.len().min(self.buf_read.len());
+                self.buf_read.drain(..cnt_drain);
+                self.buf_read.extend(rest);
+                self.stats.bytes_dropped += (cnt_skip + cnt_drain) as u64;
+                &[][..]
+            }
+            ReadOverflow::DropNewest => {
+                self.stats.bytes_dropped += rest.len() as u64;
+                &[][..]
+            }
+            ReadOverflow::StopNotify => rest,
+        };
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        self.read_cond.notify_all();
+        held
+    }
+
+    pub fn health(&self) -> Health {
+        match self.task_state {
+            TaskState::Running if self.dev_name.is_some() => Health::Connected,
+            TaskState::Running => Health::Connecting,
+            TaskState::Stopped => Health::Stopped,
+            TaskState::Panicked => Health::Panicked,
+        }
+    }
+
+    /// Snapshot of the counters, with the time of the current connection counted in.
+    pub fn stats(&self) -> LinkStats {
+        let mut stats = self.stats.clone();
+        if let Some(t) = self.connected_since {
+            stats.time_connected += t.elapsed();
+        }
+        stats
+    }
+
+    /// Takes bytes from the read buffer, as many as possible.
+    pub fn take_read(&mut self, buf: &mut [u8]) -> usize {
+        // `Read` of `VecDeque` stops at the end of the first contiguous slice
+        let cnt = buf.len().min(self.buf_read.len());
+        for (dst, src) in buf.iter_mut().zip(self.buf_read.drain(..cnt)) {
+            *dst = src;
+        }
+        self.read_taken();
+        cnt
+    }
+
+    /// No more data will be received.
+    pub fn task_ended(&self) -> bool {
+        self.task_state != TaskState::Running
+    }
+
+    /// No more data can be read: the buffer is empty and the task has ended.
+    pub fn read_ended(&self) -> bool {
+        self.buf_read.is_empty() && self.task_ended()
+    }
+
+    // wakes the pending reads and writes to check the state again
+    fn wake_io(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+        self.read_cond.notify_all();
+        self.write_cond.notify_all();
+    }
+
+    /// Takes all bytes in the read buffer.
+    pub fn drain_read(&mut self) -> Vec<u8> {
+        let data = self.buf_read.drain(..).collect::<Vec<u8>>();
+        self.read_taken();
+        data
+    }
+
+    fn read_taken(&mut self) {
+        if self.read_paused && self.buf_read.len() < self.read_capacity {
+            self.read_paused = false;
+            self.send_req(BleHdlMsg::ReqResumeRead);
+        }
+    }
+
+    /// Bytes that can be queued by `queue_write()` now.
+    pub fn write_room(&self) -> usize {
+        self.write_queue_limit.saturating_sub(self.write_pending)
+    }
+
+    /// Sends a write request to `ble_loop()` if the device is connected.
+    pub fn queue_write(&mut self, data: Vec<u8>) -> bool {
+        let len = data.len();
+        if !self.send_req(BleHdlMsg::ReqWrite(data)) {
+            return false;
+        }
+        self.write_pending += len;
+        true
+    }
+
+    // called by `ble_loop()` when queued bytes are sent or dropped
+    fn write_done(&mut self, len: usize) {
+        self.write_pending = self.write_pending.saturating_sub(len);
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+        self.write_cond.notify_all();
+    }
+
+    // called by `ble_loop()` when queued bytes can't be sent
+    fn write_failed(&mut self, data: &[u8]) {
+        self.write_undelivered.extend_from_slice(data);
+        self.write_done(data.len());
+    }
+
+    /// Returns `Ok(true)` if all queued bytes are sent, `Ok(false)` if some are still pending,
+    /// or the bytes failed to be sent since the last call.
+    pub fn check_flushed(&mut self) -> Result<bool, BleSerialError> {
+        if self.write_pending > 0 {
+            if self.task_state != TaskState::Running {
+                // the requests are dropped with the task
+                return Err(BleSerialError::Disconnected);
+            }
+            return Ok(false);
+        }
+        if !self.write_undelivered.is_empty() {
+            let undelivered = std::mem::take(&mut self.write_undelivered);
+            return Err(BleSerialError::WriteFailed { undelivered });
+        }
+        Ok(true)
+    }
+
+    /// Sends a request to `ble_loop()` if the device is connected.
+    pub fn send_req(&self, msg: BleHdlMsg) -> bool {
+        if self.dev_name.is_none() {
+            return false;
+        }
+        self.ch_req
+            .as_ref()
+            .is_some_and(|ch_req| ch_req.send(msg).is_ok())
+    }
+}
+
+/// Runs `ble_loop()` with the system's bluetooth adapter chosen by `adapter`,
+/// waiting for it if it's not present.
+pub(crate) async fn ble_loop_system_adapter(
+    adapter: AdapterSelector,
+    res: Arc<Mutex<BleSerialRes>>,
+) {
+    let mut work = RunLoop {
+        res,
+        reported: false,
+    };
+    loop {
+        work = match with_system_adapter(&adapter, work).await {
+            Ok(()) => return,
+            Err(work) => work,
+        };
+        if !work.reported {
+            debug!("ble_loop(): bluetooth adapter not found.");
+            report_error(&work.res, BleSerialError::AdapterNotFound).await;
+            raise_event(&work.res, BleSerialEvent::AdapterUnavailable).await;
+            work.reported = true;
+        }
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+    }
+}
+
+// runs `ble_loop()` once the adapter is found
+struct RunLoop {
+    res: Arc<Mutex<BleSerialRes>>,
+    reported: bool, // `AdapterUnavailable` is raised
+}
+
+impl WithTransport for RunLoop {
+    type Output = ();
+
+    async fn run<T: GattTransport>(self, transport: T) {
+        if self.reported {
+            raise_event(&self.res, BleSerialEvent::AdapterAvailable).await;
+        }
+        ble_loop(transport, self.res).await
+    }
+}
+
+pub(crate) async fn ble_loop<T: GattTransport>(adapter: T, res: Arc<Mutex<BleSerialRes>>) {
+    debug!("ble_loop(): entered.");
+
+    let (selector, config) = {
+        let lck_res = lock_res(&res);
+        (lck_res.selector.clone(), lck_res.config.clone())
+    };
+
+    let mut attempt: u32 = 0; // reconnect attempts since the last connection
+    let mut first = true;
+    let mut adapter_off = false; // `AdapterUnavailable` is raised
+    loop {
+        // create `req` (external call) message channel as soon as possible
+        let (tx_req, mut rx_req) = tokio::sync::mpsc::unbounded_channel::<BleHdlMsg>();
+        lock_res(&res).ch_req.replace(tx_req);
+        let mut msg_map = tokio_stream::StreamMap::new();
+        msg_map.insert(
+            "req",
+            tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
+                while let Some(item) = rx_req.recv().await {
+                    yield item;
+                }
+            }) as PinnedMsgStream),
+        );
+
+        // avoid useless retrying if the bluetooth device is not present
+        if !first {
+            attempt += 1;
+            let Some(next_in) = config.reconnect.delay(attempt) else {
+                debug!("ble_loop(): gave up reconnecting.");
+                raise_event(&res, BleSerialEvent::GaveUp).await;
+                return;
+            };
+            raise_event(&res, BleSerialEvent::Reconnecting { attempt, next_in }).await;
+            tokio::time::sleep(next_in).await;
+        }
+        first = false;
+
+        // wait for the adapter to be powered on (or plugged in again)
+        let available = matches!(
+            tokio::time::timeout(Duration::from_millis(500), adapter.wait_available()).await,
+            Ok(Ok(()))
+        );
+        if !available {
+            if !adapter_off {
+                debug!("ble_loop(): adapter unavailable.");
+                report_error(&res, BleSerialError::AdapterPoweredOff).await;
+                raise_event(&res, BleSerialEvent::AdapterUnavailable).await;
+                adapter_off = true;
+            }
+            while adapter.wait_available().await.is_err() {
+                debug!("ble_loop(): wait_available() failed.");
+                tokio::time::sleep(Duration::from_millis(2000)).await;
+            }
+        }
+        if adapter_off {
+            debug!("ble_loop(): adapter available.");
+            raise_event(&res, BleSerialEvent::AdapterAvailable).await;
+            adapter_off = false;
+        }
+
+        let filter = [UUID_SERV];
+        let mut discoverer = match adapter.discover_devices(&filter).await {
+            Ok(discoverer) => discoverer,
+            Err(e) => {
+                debug!("ble_loop(): discover_devices failed.");
+                report_error(&res, BleSerialError::Transport(e)).await;
+                continue;
+            }
+        };
+        debug!("ble_loop(): started discovering.");
+
+        let device = match find_device(&mut discoverer, &selector, config.discovery_timeout).await {
+            Ok(device) => device,
+            Err(e) => {
+                debug!("ble_loop(): target device not found.");
+                report_error(&res, e).await;
+                continue;
+            }
+        };
+        drop(discoverer);
+
+        // connect and get the characteristics
+        if let Err(e) = adapter.connect_device(&device).await {
+            debug!("ble_loop(): failed to connect.");
+            report_error(&res, BleSerialError::ConnectionFailed(e)).await;
+            continue;
+        }
+        // released at the end of this connection, or when the task is aborted
+        let _release = Release {
+            adapter: &adapter,
+            device: &device,
+        };
+        let chars = match device.service_characteristics(UUID_SERV).await {
+            Ok(Some(chars)) => chars,
+            Ok(None) => {
+                debug!("ble_loop(): cannot find the correct service (unexpected).");
+                report_error(&res, BleSerialError::ServiceMissing).await;
+                continue;
+            }
+            Err(e) => {
+                debug!("ble_loop(): cannot get service characteristics (unexpected).");
+                report_error(&res, BleSerialError::Transport(e)).await;
+                continue;
+            }
+        };
+        let take = |uuid| {
+            chars
+                .iter()
+                .find(|ch| ch.uuid() == uuid)
+                .cloned()
+                .ok_or(BleSerialError::CharacteristicMissing(uuid))
+        };
+        let (char_baud, char_read, char_write) = match (
+            take(UUID_CHAR_BAUD),
+            take(UUID_CHAR_READ),
+            take(UUID_CHAR_WRITE),
+        ) {
+            (Ok(ch_baud), Ok(ch_read), Ok(ch_write)) => (ch_baud, ch_read, ch_write),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                debug!("ble_loop(): incorrect characteristics.");
+                report_error(&res, e).await;
+                continue;
+            }
+        };
+
+        match read_baud(&char_baud).await {
+            Ok(baud) => lock_res(&res).baud_rate = baud,
+            Err(e) => {
+                debug!("ble_loop(): failed to check baud rate.");
+                report_error(&res, BleSerialError::Transport(e)).await;
+                continue;
+            }
+        }
+
+        // enable read notification
+        let Ok(stream_notify_read) = char_read.notify().await else {
+            debug!("ble_loop(): failed to enable notification of char_read.");
+            report_error(&res, BleSerialError::NotifySubscribeFailed).await;
+            continue;
+        };
+
+        // create UART read notification stream
+        msg_map.insert("read", read_msg_stream(stream_notify_read));
+        // received bytes that didn't fit in the buffer with `ReadOverflow::StopNotify`
+        let mut read_held: Vec<u8> = Vec::new();
+        msg_map.insert(
+            "timer",
+            tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
+                loop {
+                    tokio::time::sleep(config.check_interval).await;
+                    yield BleHdlMsg::Timer;
+                }
+            }) as PinnedMsgStream),
+        );
+        if let Ok(mut stream_adapter) = adapter.adapter_events().await {
+            msg_map.insert(
+                "adapter",
+                tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
+                    while let Some(powered) = stream_adapter.next().await {
+                        yield BleHdlMsg::AdapterPowered(powered);
+                    }
+                }) as PinnedMsgStream),
+            );
+        }
+
+        // get device name and indicate for connection
+        let dev_name = device.name().await.unwrap_or("unknown".to_string());
+        {
+            let mut lck_res = lock_res(&res);
+            lck_res.dev_name.replace(dev_name);
+            lck_res.last_error.take();
+            if lck_res.connected_once {
+                lck_res.stats.reconnects += 1;
+            }
+            lck_res.connected_once = true;
+            lck_res.connected_since.replace(std::time::Instant::now());
+        }
+        attempt = 0;
+        raise_event(&res, BleSerialEvent::Connect).await;
+
+        // handle messages
+        while let Some((key, msg)) = msg_map.next().await {
+            let Some(msg) = msg else {
+                debug!("ble_loop(): stream {key} ends, breaking.");
+                break; // the BLE connection is broken, or the req stream is broken
+            };
+            if key == "read" {
+                // read notification
+                if let BleHdlMsg::ReadNotify(data) = msg {
+                    let mut held = {
+                        let mut lck_res = lock_res(&res);
+                        lck_res.stats.record_notification(data.len());
+                        let held = lck_res.push_read(&data);
+                        if !held.is_empty() {
+                            lck_res.read_paused = true;
+                        }
+                        held.to_vec()
+                    };
+                    let mut queued = Vec::new();
+                    if !held.is_empty() {
+                        // dropping the stream disables the notification; notifications
+                        // already received are held as well
+                        debug!("ble_loop(): read buffer is full, stop notification.");
+                        if let Some(mut stream_read) = msg_map.remove("read") {
+                            while let Some(Some(Some(BleHdlMsg::ReadNotify(data)))) =
+                                stream_read.next().now_or_never()
+                            {
+                                queued.push(data);
+                            }
+                        }
+                        let mut lck_res = lock_res(&res);
+                        for data in queued.iter() {
+                            lck_res.stats.record_notification(data.len());
+                            held.extend_from_slice(data);
+                        }
+                        read_held = held;
+                    }
+                    raise_event(&res, BleSerialEvent::Receive(data)).await;
+                    for data in queued {
+                        raise_event(&res, BleSerialEvent::Receive(data)).await;
+                    }
+                } else if let BleHdlMsg::ReadFailed(e) = msg {
+                    debug!("ble_loop(): notification failed, breaking.");
+                    report_error(&res, BleSerialError::Transport(e)).await;
+                    break;
+                }
+                continue;
+            } else if key == "adapter" {
+                if let BleHdlMsg::AdapterPowered(false) = msg {
+                    debug!("ble_loop(): adapter powered off, breaking.");
+                    report_error(&res, BleSerialError::AdapterPoweredOff).await;
+                    raise_event(&res, BleSerialEvent::AdapterUnavailable).await;
+                    adapter_off = true;
+                    break;
+                }
+                continue;
+            } else if key == "timer" {
+                // connection checker
+                if !device.is_connected().await {
+                    debug!("ble_loop(): disconnected, breaking.");
+                    break;
+                }
+                continue;
+            }
+            match msg {
+                // request message
+                BleHdlMsg::ReqSetBaud(baud) => {
+                    lock_res(&res).stats.baud_set_attempts += 1;
+                    let expected = expected_baud(baud);
+                    for _ in 0..3 {
+                        if expected.is_none() {
+                            break; // it would be ignored by the firmware
+                        }
+                        if char_baud
+                            .write_without_response(&baud.to_le_bytes())
+                            .await
+                            .is_ok()
+                        {
+                            break;
+                        }
+                    }
+                    let mut suc = false;
+                    for _ in 0..config.baud_verify_attempts {
+                        if expected.is_none() {
+                            break;
+                        }
+                        let cur_baud = read_baud(&char_baud).await.unwrap_or(0);
+                        if Some(cur_baud) == expected {
+                            debug!("ble_loop(): baudrate set.");
+                            lock_res(&res).baud_rate = cur_baud;
+                            suc = true;
+                            break;
+                        } else {
+                            tokio::time::sleep(config.baud_verify_interval).await;
+                        }
+                    }
+                    if !suc {
+                        debug!("ble_loop(): failed to set baud rate.");
+                        let actual = {
+                            let mut lck_res = lock_res(&res);
+                            lck_res.stats.baud_set_failures += 1;
+                            lck_res.baud_rate
+                        };
+                        report_error(
+                            &res,
+                            BleSerialError::BaudRejected {
+                                requested: baud,
+                                actual,
+                            },
+                        )
+                        .await;
+                    }
+                }
+                BleHdlMsg::ReqWrite(data) => {
+                    // split into chunks of ATT_MTU - 3 bytes, stop at the first failed chunk
+                    let chunk_size = char_write.max_write_len().await.unwrap_or(20).max(1);
+                    let write_mode = lock_res(&res).write_mode;
+                    let mut cnt_sent = 0;
+                    for chunk in data.chunks(chunk_size) {
+                        let mut suc = false;
+                        let mut cnt_attempts = 0;
+                        for _ in 0..config.write_attempts.max(1) {
+                            cnt_attempts += 1;
+                            let result = match write_mode {
+                                WriteMode::WithResponse => char_write.write(chunk).await,
+                                WriteMode::WithoutResponse => {
+                                    char_write.write_without_response(chunk).await
+                                }
+                            };
+                            if result.is_ok() {
+                                suc = true;
+                                break;
+                            }
+                        }
+                        let mut lck_res = lock_res(&res);
+                        if !suc {
+                            lck_res.stats.write_retries += cnt_attempts - 1;
+                            lck_res.stats.write_failures += 1;
+                            break;
+                        }
+                        cnt_sent += chunk.len();
+                        lck_res.stats.write_retries += cnt_attempts - 1;
+                        lck_res.stats.bytes_written += chunk.len() as u64;
+                        lck_res.write_done(chunk.len());
+                    }
+                    if cnt_sent < data.len() {
+                        debug!("ble_loop(): write failed.");
+                        lock_res(&res).write_failed(&data[cnt_sent..]);
+                        raise_event(&res, BleSerialEvent::WriteFailed(data[cnt_sent..].to_vec()))
+                            .await;
+                    }
+                }
+                BleHdlMsg::ReqResumeRead => {
+                    if msg_map.contains_key("read") {
+                        continue;
+                    }
+                    {
+                        let mut lck_res = lock_res(&res);
+                        read_held = lck_res.push_read(&read_held).to_vec();
+                        if !read_held.is_empty() {
+                            lck_res.read_paused = true;
+                            continue;
+                        }
+                    }
+                    debug!("ble_loop(): resume read notification.");
+                    let Ok(stream_notify_read) = char_read.notify().await else {
+                        debug!("ble_loop(): failed to enable notification of char_read.");
+                        report_error(&res, BleSerialError::NotifySubscribeFailed).await;
+                        break;
+                    };
+                    msg_map.insert("read", read_msg_stream(stream_notify_read));
+                }
+                _ => (),
+            }
+        }
+
+        // the adapter event may arrive after the connection is broken
+        if let Some(mut stream_adapter) = msg_map.remove("adapter") {
+            while let Some(Some(Some(msg))) = stream_adapter.next().now_or_never() {
+                if let BleHdlMsg::AdapterPowered(false) = msg {
+                    debug!("ble_loop(): adapter powered off.");
+                    report_error(&res, BleSerialError::AdapterPoweredOff).await;
+                    raise_event(&res, BleSerialEvent::AdapterUnavailable).await;
+                    adapter_off = true;
+                    break;
+                }
+            }
+        }
+
+        // indicate disconnection
+        {
+            let mut lck_res = lock_res(&res);
+            lck_res.dev_name.take();
+            lck_res.read_paused = false;
+            if let Some(t) = lck_res.connected_since.take() {
+                lck_res.stats.time_connected += t.elapsed();
+            }
+            lck_res.stats.bytes_dropped += read_held.len() as u64;
+            lck_res.wake_io();
+        }
+        debug!("ble_loop(): disconnected.");
+        raise_event(&res, BleSerialEvent::Disconnect).await;
+
+        // no more requests can be sent now; fail the remaining write requests
+        if let Some(mut stream_req) = msg_map.remove("req") {
+            while let Some(Some(Some(msg))) = stream_req.next().now_or_never() {
+                if let BleHdlMsg::ReqWrite(data) = msg {
+                    lock_res(&res).write_failed(&data);
+                    raise_event(&res, BleSerialEvent::WriteFailed(data)).await;
+                }
+            }
+        }
+    }
+}
+
+// tells the transport that the connected device is no longer used
+struct Release<'a, T: GattTransport> {
+    adapter: &'a T,
+    device: &'a T::Device,
+}
+
+impl<T: GattTransport> Drop for Release<'_, T> {
+    fn drop(&mut self) {
+        self.adapter.release_device(self.device);
+    }
+}
+
+fn read_msg_stream(
+    mut stream_notify_read: BoxStream<'static, TransportResult<Vec<u8>>>,
+) -> tokio_stream::StreamNotifyClose<PinnedMsgStream<'static>> {
+    tokio_stream::StreamNotifyClose::new(Box::pin(async_stream::stream! {
+        while let Some(item) = stream_notify_read.next().await {
+            match item {
+                Ok(data) => yield BleHdlMsg::ReadNotify(data),
+                Err(e) => {
+                    yield BleHdlMsg::ReadFailed(e);
+                    break;
+                }
+            }
+        }
+    }) as PinnedMsgStream)
+}
+
+// discovers for up to `timeout`; for selectors that may match multiple devices,
+// other matching devices are still collected for 1 s after the first match.
+async fn find_device<D: GattDevice>(
+    discoverer: &mut BoxStream<'_, TransportResult<D>>,
+    selector: &DeviceSelector,
+    timeout: Duration,
+) -> Result<D, BleSerialError> {
+    let mut found: Vec<D> = Vec::new();
+    let mut t_end = Instant::now() + timeout;
+    while let Ok(Some(item)) = tokio::time::timeout_at(t_end, discoverer.next()).await {
+        let Ok(dev) = item else {
+            continue;
+        };
+        let id = dev.id();
+        debug!("ble_loop(): found {}.", &id);
+        if found.iter().any(|d| d.id() == id) {
+            continue;
+        }
+        let name = dev.name().await.ok();
+        if !selector.matches(&id, name.as_deref()) {
+            continue;
+        }
+        found.push(dev);
+        if selector.is_unique() {
+            break;
+        }
+        if found.len() == 1 {
+            t_end = t_end.min(Instant::now() + Duration::from_millis(1000));
+        }
+    }
+    if found.len() > 1 {
+        return Err(BleSerialError::AmbiguousDevice(
+            found.iter().map(|d| d.id()).collect(),
+        ));
+    }
+    found.pop().ok_or(BleSerialError::DeviceNotFound)
+}
+
+async fn read_baud(char_baud: &impl GattCharacteristic) -> TransportResult<u32> {
+    let mut err = TransportError::new("invalid baud rate value");
+    for _ in 0..3 {
+        match char_baud.read().await {
+            Ok(bytes_baud) => {
+                if bytes_baud.len() < 4 {
+                    continue;
+                }
+                let mut arr_baud = [0u8; 4];
+                arr_baud.copy_from_slice(&bytes_baud[..4]);
+                return Ok(u32::from_le_bytes(arr_baud));
+            }
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+// records the error and raises `BleSerialEvent::Error`
+pub(crate) async fn report_error(res: &Arc<Mutex<BleSerialRes>>, e: BleSerialError) {
+    lock_res(res).last_error.replace(e.clone());
+    raise_event(res, BleSerialEvent::Error(e)).await;
+}
+
+// delivers the event to the subscribers, and queues it for the handler;
+// waits for a slow handler without holding the lock, nothing is dropped
+async fn raise_event(res: &Arc<Mutex<BleSerialRes>>, evt: BleSerialEvent) {
+    let (on_event, ch_event) = {
+        let mut lck_res = lock_res(res);
+        // fails only if there's no subscriber
+        let _ = lck_res.subscribers.send(evt.clone());
+        (lck_res.on_event.clone(), lck_res.event_sender())
+    };
+    let _ = ch_event.send((on_event, evt)).await;
+}
+
+// the background task keeps working if the lock is poisoned by a panicking thread
+fn lock_res(res: &Mutex<BleSerialRes>) -> MutexGuard<'_, BleSerialRes> {
+    res.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs the background task `f_loop`, recording how it ends in `task_state`.
+pub(crate) async fn run_task(res: Arc<Mutex<BleSerialRes>>, f_loop: impl Future<Output = ()>) {
+    let result = AssertUnwindSafe(f_loop).catch_unwind().await;
+    res.clear_poison();
+    let was_connected = {
+        let mut lck_res = lock_res(&res);
+        lck_res.task_state = if result.is_ok() {
+            TaskState::Stopped
+        } else {
+            TaskState::Panicked
+        };
+        lck_res.ch_req.take();
+        if let Some(t) = lck_res.connected_since.take() {
+            lck_res.stats.time_connected += t.elapsed();
+        }
+        lck_res.wake_io();
+        lck_res.dev_name.take().is_some()
+    };
+    if result.is_err() {
+        debug!("ble_loop(): panicked.");
+        if was_connected {
+            raise_event(&res, BleSerialEvent::Disconnect).await;
+        }
+        report_error(&res, BleSerialError::TaskPanicked).await;
+    }
+}
+
+/// Baud rate the firmware sets for `requested`, or `None` if it's rejected.
+pub(crate) fn expected_baud(requested: u32) -> Option<u32> {
+    rtlbaud::auto_calc(requested).map(|conf| conf.baud_actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        fake::{wait_event, wait_until, FakePeripheral, FakeTransport},
+        BleSerial, BleSerialBuilder, BleSerialError, BleSerialEvent, DeviceSelector, Health,
+        ReadOverflow, ReconnectPolicy,
+    };
+
+    fn open_with_overflow(fake: &FakePeripheral, policy: ReadOverflow) -> BleSerial {
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .read_buf_capacity(10)
+            .read_overflow(policy)
+            .build_with_transport(fake.transport())
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        ble_ser
+    }
+
+    #[test]
+    fn overflow_drop_oldest() {
+        let fake = FakePeripheral::default();
+        let ble_ser = open_with_overflow(&fake, ReadOverflow::DropOldest);
+        fake.push_uart_rx(b"0123456789abcde");
+        assert!(wait_until(|| ble_ser.stats().bytes_received == 15));
+        assert_eq!(ble_ser.drain_read_buf(), b"56789abcde");
+        assert_eq!(ble_ser.dropped_read_bytes(), 5);
+    }
+
+    #[test]
+    fn overflow_drop_newest() {
+        let fake = FakePeripheral::default();
+        let ble_ser = open_with_overflow(&fake, ReadOverflow::DropNewest);
+        fake.push_uart_rx(b"0123456789abcde");
+        assert!(wait_until(|| ble_ser.stats().bytes_received == 15));
+        assert_eq!(ble_ser.drain_read_buf(), b"0123456789");
+        assert_eq!(ble_ser.dropped_read_bytes(), 5);
+    }
+
+    #[test]
+    fn overflow_stop_notify() {
+        let fake = FakePeripheral::default();
+        let ble_ser = open_with_overflow(&fake, ReadOverflow::StopNotify);
+        let data: Vec<u8> = (0..50).collect();
+        {
+            // all 3 notifications are queued before the first one is handled
+            let _lck_res = ble_ser.res.lock().unwrap();
+            fake.push_uart_rx(&data);
+        }
+        assert!(wait_until(|| ble_ser.stats().bytes_received == 50));
+        // discarded by the bridge while the notification is disabled
+        fake.push_uart_rx(b"lost");
+
+        let mut received = Vec::new();
+        assert!(wait_until(|| {
+            received.extend(ble_ser.drain_read_buf());
+            received.len() >= 50
+        }));
+        assert_eq!(received, data);
+        assert_eq!(ble_ser.dropped_read_bytes(), 0);
+
+        // resumed after the buffer is drained
+        received.clear();
+        assert!(wait_until(|| {
+            fake.push_uart_rx(b"x");
+            received.extend(ble_ser.drain_read_buf());
+            !received.is_empty()
+        }));
+        assert!(received.iter().all(|&b| b == b'x'));
+    }
+
+    #[test]
+    fn adapter_power_off() {
+        let fake = FakePeripheral::default();
+        let transport = fake.transport();
+        let adapter = transport.adapter();
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .reconnect_policy(ReconnectPolicy::Fixed {
+                delay: Duration::from_millis(50),
+                max_attempts: None,
+            })
+            .build_with_transport(transport)
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let mut events = ble_ser.subscribe().unwrap();
+
+        adapter.set_powered(false);
+        let received = wait_event(&mut events, |evt| {
+            matches!(evt, BleSerialEvent::AdapterUnavailable)
+        });
+        assert!(received.iter().any(|evt| matches!(
+            evt,
+            BleSerialEvent::Error(BleSerialError::AdapterPoweredOff)
+        )));
+        assert!(wait_until(|| !ble_ser.is_connected()));
+
+        adapter.set_powered(true);
+        let received = wait_event(&mut events, |evt| matches!(evt, BleSerialEvent::Connect));
+        assert!(received
+            .iter()
+            .any(|evt| matches!(evt, BleSerialEvent::AdapterAvailable)));
+        assert!(!received
+            .iter()
+            .any(|evt| matches!(evt, BleSerialEvent::AdapterUnavailable)));
+        assert!(ble_ser.is_connected());
+    }
+
+    #[test]
+    fn ambiguous_device() {
+        let fakes = [
+            FakePeripheral::new("00:E0:02:00:00:0A", "RTL-UART-00000A"),
+            FakePeripheral::new("00:E0:02:00:00:0B", "RTL-UART-00000B"),
+        ];
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::NamePrefix("RTL-UART-".to_string()))
+            .reconnect_policy(ReconnectPolicy::Never)
+            .build_with_transport(FakeTransport::new(&fakes))
+            .unwrap();
+        let mut events = ble_ser.subscribe().unwrap();
+        wait_event(&mut events, |evt| matches!(evt, BleSerialEvent::GaveUp));
+        let Some(BleSerialError::AmbiguousDevice(mut ids)) = ble_ser.last_error() else {
+            panic!("unexpected error: {:?}", ble_ser.last_error());
+        };
+        ids.sort();
+        assert_eq!(ids, ["00:E0:02:00:00:0A", "00:E0:02:00:00:0B"]);
+    }
+
+    #[test]
+    fn two_subscribers() {
+        let fake = FakePeripheral::default();
+        fake.set_present(false);
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .build_with_transport(fake.transport())
+            .unwrap();
+        let mut subscribers = [ble_ser.subscribe().unwrap(), ble_ser.subscribe().unwrap()];
+        fake.set_present(true);
+        assert!(wait_until(|| ble_ser.is_connected()));
+        fake.push_uart_rx(b"hello");
+
+        for events in subscribers.iter_mut() {
+            let events = wait_event(events, |evt| matches!(evt, BleSerialEvent::Receive(_)));
+            let events: Vec<_> = events
+                .into_iter()
+                .filter(|evt| !matches!(evt, BleSerialEvent::Error(_)))
+                .collect();
+            assert!(
+                matches!(&events[..], [BleSerialEvent::Connect, BleSerialEvent::Receive(data)] if data == b"hello"),
+                "{events:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn slow_event_handler() {
+        let fake = FakePeripheral::default();
+        fake.set_present(false);
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .build_with_transport(fake.transport())
+            .unwrap();
+        let gate = Arc::new(Mutex::new(()));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let (gate_clone, handled_clone) = (gate.clone(), handled.clone());
+        ble_ser
+            .on_event(move |evt| {
+                drop(gate_clone.lock().unwrap());
+                handled_clone.lock().unwrap().push(evt);
+            })
+            .unwrap();
+        fake.set_present(true);
+        assert!(wait_until(|| ble_ser.is_connected()));
+
+        // more events than the dispatcher queue holds arrive while the handler is blocked
+        let lck_gate = gate.lock().unwrap();
+        let data: Vec<[u8; 2]> = (0..300u16).map(u16::to_be_bytes).collect();
+        for d in data.iter() {
+            fake.push_uart_rx(d);
+        }
+        thread::sleep(Duration::from_millis(200));
+        drop(lck_gate);
+
+        assert!(wait_until(|| handled.lock().unwrap().len() >= 301));
+        let handled = handled.lock().unwrap();
+        let received: Vec<&[u8]> = handled
+            .iter()
+            .filter_map(|evt| match evt {
+                BleSerialEvent::Receive(d) => Some(&d[..]),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<&[u8]> = data.iter().map(|d| &d[..]).collect();
+        assert_eq!(received, expected);
+        assert!(matches!(
+            handled
+                .iter()
+                .find(|evt| !matches!(evt, BleSerialEvent::Error(_))),
+            Some(BleSerialEvent::Connect)
+        ));
+    }
+
+    #[test]
+    fn health_after_panics() {
+        let fake = FakePeripheral::default();
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .build_with_transport(fake.transport())
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+
+        // a user thread panicking with the lock held doesn't stop the task
+        thread::scope(|s| {
+            let panicking = s.spawn(|| {
+                let _lck_res = ble_ser.res.lock().unwrap();
+                panic!("user thread panicked");
+            });
+            assert!(panicking.join().is_err());
+        });
+        assert!(ble_ser.res.is_poisoned());
+        assert_eq!(ble_ser.health(), Health::Connected);
+        ble_ser.res.clear_poison();
+
+        fake.panic_next_write();
+        (&ble_ser).write_all(b"x").unwrap();
+        assert!(wait_until(|| ble_ser.health() == Health::Panicked));
+        assert!(matches!(
+            ble_ser.last_error(),
+            Some(BleSerialError::TaskPanicked)
+        ));
+        assert!(!ble_ser.is_connected());
+    }
+}
@@ -0,0 +1,124 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::broadcast;
+
+use crate::{
+    link::BleSerialRes, BleSerialError, BleSerialEvent, Health, LinkStats, ReadOverflow, WriteMode,
+};
+
+/// State and settings of the link, shared by [`crate::BleSerial`] and
+/// [`crate::AsyncBleSerial`], which dereference to it.
+pub struct LinkHandle {
+    pub(crate) res: Arc<Mutex<BleSerialRes>>,
+}
+
+impl LinkHandle {
+    pub fn is_connected(&self) -> bool {
+        self.device_name().is_some()
+    }
+
+    pub fn device_name(&self) -> Option<String> {
+        if let Ok(lck_res) = self.res.lock() {
+            lck_res.dev_name.clone()
+        } else {
+            None
+        }
+    }
+
+    pub fn baud_rate(&self) -> Option<u32> {
+        let lck_res = self.res.lock().ok()?;
+        lck_res.dev_name.as_ref().map(|_| lck_res.baud_rate)
+    }
+
+    /// Whether the background task is still alive, and connected to the device.
+    pub fn health(&self) -> Health {
+        // a lock poisoned by another thread doesn't stop the task
+        self.res
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .health()
+    }
+
+    /// The last error occurred in the background task; it's cleared on connection.
+    pub fn last_error(&self) -> Option<BleSerialError> {
+        let lck_res = self.res.lock().unwrap_or_else(PoisonError::into_inner);
+        lck_res.last_error.clone()
+    }
+
+    pub fn drain_read_buf(&self) -> Vec<u8> {
+        if let Ok(mut lck_res) = self.res.lock() {
+            lck_res.drain_read()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Sets the callback of events, replacing the previous one. See [`LinkHandle::subscribe`]
+    /// for multiple listeners. Events are delivered in order on a dedicated thread; a slow
+    /// callback holds back the background task, which shouldn't be waited for in the callback.
+    pub fn on_event(
+        &self,
+        f: impl Fn(BleSerialEvent) + 'static + Send + Sync,
+    ) -> Result<(), BleSerialError> {
+        let mut lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        lck_res.on_event = Arc::new(Box::new(f));
+        Ok(())
+    }
+
+    /// Returns a receiver of the events raised from now on; each subscriber gets its own
+    /// copy. It ends when `BleSerial` or `AsyncBleSerial` is dropped, and misses the older
+    /// events (`RecvError::Lagged`) if it falls more than 256 events behind.
+    pub fn subscribe(&self) -> Result<broadcast::Receiver<BleSerialEvent>, BleSerialError> {
+        let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        Ok(lck_res.subscribe())
+    }
+
+    /// Sets the capacity of the read buffer, 64 KiB by default.
+    pub fn set_read_buf_capacity(&self, capacity: usize) {
+        if let Ok(mut lck_res) = self.res.lock() {
+            lck_res.read_capacity = capacity.max(1);
+        }
+    }
+
+    pub fn set_read_overflow(&self, policy: ReadOverflow) {
+        if let Ok(mut lck_res) = self.res.lock() {
+            lck_res.read_overflow = policy;
+        }
+    }
+
+    /// Counters of the link for diagnosis.
+    pub fn stats(&self) -> LinkStats {
+        self.res
+            .lock()
+            .map_or(LinkStats::default(), |lck_res| lck_res.stats())
+    }
+
+    /// Received bytes discarded because the read buffer was full.
+    pub fn dropped_read_bytes(&self) -> u64 {
+        self.res
+            .lock()
+            .map_or(0, |lck_res| lck_res.stats.bytes_dropped)
+    }
+
+    pub fn set_write_mode(&self, mode: WriteMode) {
+        if let Ok(mut lck_res) = self.res.lock() {
+            lck_res.write_mode = mode;
+        }
+    }
+
+    /// Sets the maximum amount of bytes queued by `write()` but not yet sent, 4096 by default;
+    /// writing waits when the queue is full.
+    pub fn set_write_queue_limit(&self, limit: usize) {
+        if let Ok(mut lck_res) = self.res.lock() {
+            lck_res.write_queue_limit = limit.max(1);
+        }
+    }
+
+    /// Bytes queued by `write()` but not yet sent.
+    pub fn bytes_to_write(&self) -> usize {
+        self.res.lock().map_or(0, |lck_res| lck_res.write_pending)
+    }
+}
@@ -1,142 +1,449 @@
 // by wuwbobo2021 <wuwbobo@outlook.com>
 // for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
 
+mod eol;
+#[cfg(target_os = "linux")]
+mod pty;
+mod rfc2217;
+mod tcp;
+mod tty;
+
+// prints a line of message, also when the console is in raw mode
+macro_rules! status {
+    ($($arg:tt)+) => (tty::print_status(&format!($($arg)+)))
+}
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use hex::{FromHex, ToHex};
 use std::{
-    io::{self, Write},
-    sync::{Arc, Mutex},
+    any::Any,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Weak,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
+};
+
+use eol::{RxEol, RxTranslator, TxEol};
+use rtl8762c_ble_uart_host::{
+    AdapterSelector, BleSerial, BleSerialBuilder, BleSerialError, BleSerialEvent, DeviceSelector,
+    DiscoveredBridge, Health, LinkStats,
 };
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+const SCAN_TIMEOUT_SECS: &str = "5";
+const CONNECT_TIMEOUT_SECS: &str = "20";
+const READ_TIMEOUT_MS: u64 = 500;
+const EVENT_POLL_MS: u64 = 100; // events are checked while waiting for data in the terminal
+const STATS_INTERVAL_MS: u64 = 10 * 1000;
+
+// clap exits with 2 for usage errors
+const EXIT_NOT_FOUND: u8 = 3;
+const EXIT_BAUD_REJECTED: u8 = 4;
+const EXIT_IO_ERROR: u8 = 5;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes: 0 success, 1 other error, 2 usage error, 3 adapter or device not found,
+            4 baud rate rejected, 5 I/O error (connection failed or lost, write failed)";
+
+/// Serial terminal and tools for the rtl8762c-ble-uart bridge.
+/// Without a subcommand, `term` options are accepted for compatibility.
+#[derive(Parser)]
+#[command(
+    name = "rtl8762c-bleser",
+    version,
+    after_help = EXIT_CODES_HELP,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    term: Option<TermArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Interactive terminal, or a PTY / TCP bridge
+    Term(TermArgs),
+    /// List nearby RTL-UART bridges
+    Scan {
+        #[arg(short, long, default_value_t = AdapterSelector::Default)]
+        adapter: AdapterSelector,
+        /// Scanning time in seconds
+        #[arg(short, long, default_value = SCAN_TIMEOUT_SECS, value_parser = parse_secs)]
+        timeout: Duration,
+    },
+    /// Print the baud rate of the bridge's UART, or set it
+    Baud {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        baud_rate: Option<u32>,
+    },
+    /// Send data (or stdin if not given), exit after it is delivered
+    Send {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+        baud_rate: Option<u32>,
+        /// Data is given in hex, like `01 02 ff`
+        #[arg(short = 'x', long)]
+        hex: bool,
+        data: Option<String>,
+    },
+    /// Write received data to stdout
+    Monitor {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+        baud_rate: Option<u32>,
+        /// Print data in hex, a line for each notification
+        #[arg(short = 'x', long)]
+        hex: bool,
+        /// Exit after the given seconds
+        #[arg(long, value_parser = parse_secs)]
+        duration: Option<Duration>,
+    },
+}
+
+#[derive(Args)]
+struct LinkArgs {
+    /// Device id, or addr:<id>, name:<name>, prefix:<name prefix>, mac:<hex suffix>, first
+    #[arg(short = 'u', long)]
+    device: DeviceSelector,
+    /// Bluetooth adapter name (Linux) or index
+    #[arg(short, long, default_value_t = AdapterSelector::Default)]
+    adapter: AdapterSelector,
+}
+
+#[derive(Args)]
+struct ConnectArgs {
+    #[command(flatten)]
+    link: LinkArgs,
+    /// Seconds to wait for the connection
+    #[arg(long, default_value = CONNECT_TIMEOUT_SECS, value_parser = parse_secs)]
+    timeout: Duration,
+}
+
+#[derive(Args)]
+struct TermArgs {
+    #[command(flatten)]
+    link: LinkArgs,
+    /// Baud rate to be set on each connection
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    baud_rate: Option<u32>,
+    /// Hex mode
+    #[arg(short = 'x', long)]
+    hex: bool,
+    /// Line ending appended to each line sent in text mode
+    #[arg(long, value_enum, default_value_t = TxEol::Lf)]
+    eol: TxEol,
+    /// Translation of received line endings into LF
+    #[arg(long, value_enum, default_value_t = RxEol::None)]
+    in_eol: RxEol,
+    /// Print received data as is, without the prefix and newlines between notifications
+    #[arg(long)]
+    raw: bool,
+    /// Put the console in raw mode and send every keystroke; Ctrl-A Ctrl-H for help
+    #[arg(short, long, conflicts_with_all = ["pty", "tcp_listen"])]
+    interactive: bool,
+    /// Print link statistics periodically
+    #[arg(long)]
+    stats: bool,
+    /// Create a pseudo-terminal for other programs, symlinked at <LINK> if given (Linux)
+    #[arg(long, value_name = "LINK", num_args = 0..=1)]
+    pty: Option<Option<PathBuf>>,
+    /// Serve the link to one TCP client at a time, e.g. 0.0.0.0:2217
+    #[arg(long, value_name = "ADDR", conflicts_with = "pty")]
+    tcp_listen: Option<String>,
+    /// Use Telnet COM port control (RFC 2217) instead of raw TCP
+    #[arg(long, requires = "tcp_listen")]
+    rfc2217: bool,
+}
 
-use rtl8762c_ble_uart_host::{BleSerial, BleSerialEvent};
-
-const PROMPT_USAGE: &str = " \
-Usage: -u <device_uuid> [-b <baud_rate>] [-h]
-\t-h\tHex mode
-";
-
-fn main() {
-    let (dev_bt_addr, baud_rate, read_timeout_ms, hex_mode, clear_on_disc) = {
-        let mut dev_bt_addr: Option<String> = None;
-        let mut baud_rate: Option<u32> = None;
-        let read_timeout_ms = 500; // timeout value for io::Read, makes no difference here
-        let mut hex_mode = false;
-        let clear_on_disc = false; // makes no difference in this program
-
-        let mut args = std::env::args();
-        let _ = args.next(); //skip program path
-        while let Some(s) = args.next() {
-            match &s as &str {
-                "-u" => dev_bt_addr = Some(args.next().unwrap()),
-                "-b" => baud_rate = Some(args.next().unwrap().trim().parse().unwrap()),
-                "-h" => hex_mode = true,
-                _ => (),
-            }
-        }
-        if dev_bt_addr.is_none() {
-            print!("{}", PROMPT_USAGE);
-            return;
-        }
-        (
-            dev_bt_addr.unwrap(),
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Term(args)) => run_term(args),
+        Some(Command::Scan { adapter, timeout }) => scan_and_print(&adapter, timeout),
+        Some(Command::Baud { conn, baud_rate }) => run_baud(conn, baud_rate),
+        Some(Command::Send {
+            conn,
             baud_rate,
-            read_timeout_ms,
-            hex_mode,
-            clear_on_disc,
-        )
+            hex,
+            data,
+        }) => run_send(conn, baud_rate, hex, data),
+        Some(Command::Monitor {
+            conn,
+            baud_rate,
+            hex,
+            duration,
+        }) => run_monitor(conn, baud_rate, hex, duration),
+        None => match cli.term {
+            Some(args) => run_term(args),
+            None => {
+                let _ = Cli::command().print_help();
+                ExitCode::from(2)
+            }
+        },
+    }
+}
+
+fn run_term(args: TermArgs) -> ExitCode {
+    let TermArgs {
+        link,
+        baud_rate,
+        hex: hex_mode,
+        eol,
+        in_eol,
+        raw: raw_mode,
+        interactive,
+        stats: stats_mode,
+        pty,
+        tcp_listen,
+        rfc2217,
+    } = args;
+    let clear_on_disc = false; // makes no difference in this program
+    let (pty_mode, pty_link) = (pty.is_some(), pty.flatten());
+    if pty_mode && cfg!(not(target_os = "linux")) {
+        println!("BleSerial: --pty is only supported on Linux");
+        return ExitCode::from(2);
+    }
+
+    let ble_ser = match BleSerialBuilder::new(link.device)
+        .adapter(link.adapter)
+        .read_timeout(Duration::from_millis(EVENT_POLL_MS))
+        .build()
+    {
+        Ok(ble_ser) => Arc::new(ble_ser),
+        Err(e) => {
+            println!("BleSerial: {e}");
+            return exit_code(&e);
+        }
     };
+    let mut events = ble_ser.subscribe().unwrap();
 
-    let ble_ser = Arc::new(Mutex::new(
-        BleSerial::build(&dev_bt_addr, Duration::from_millis(read_timeout_ms)).unwrap(),
-    ));
-
-    // clone the Arc smart pointer `ble_ser` for on_event()'s closure
-    // without downgrading causes memory leak and forced shutdown on exit
-    let ble_ser_weak = Arc::<Mutex<BleSerial>>::downgrade(&ble_ser);
-    ble_ser
-        .lock()
-        .unwrap()
-        .on_event(move |evt| {
-            let ble_ser = if let Some(s) = ble_ser_weak.upgrade() {
-                s
-            } else {
-                return;
-            };
-            match evt {
-                BleSerialEvent::Connect => {
-                    println!("BleSerial Event: Connected");
-                    if let Some(baud) = baud_rate {
-                        match ble_ser.lock().unwrap().set_baud_rate(baud) {
-                            Ok(b) => {
-                                println!("BleSerial: Baudrate set. expected: {baud} current: {b}")
-                            }
-                            Err(opt_b) => println!(
-                                "BleSerial: Baudrate not set. expected: {} current: {:?}",
-                                baud, opt_b
-                            ),
-                        }
-                    } else {
-                        println!(
-                            "BleSerial: Baudrate {}",
-                            ble_ser.lock().unwrap().baud_rate().unwrap()
-                        );
+    // in PTY or TCP mode, received data is taken by the bridge;
+    // the PTY bridge also sets the baud rate
+    let bridged = pty_mode || tcp_listen.is_some();
+    let baud_on_connect = if pty_mode { None } else { baud_rate };
+    // kept until quitting; its threads stop when `ble_ser` is dropped
+    let _bridge: Option<Box<dyn Any>> = if bridged {
+        let ble_ser_weak = Arc::downgrade(&ble_ser);
+        let bridge_events = ble_ser.subscribe().unwrap();
+        let bridge = match tcp_listen {
+            Some(addr) => start_tcp_bridge(ble_ser_weak, &addr, rfc2217, bridge_events),
+            #[cfg(target_os = "linux")]
+            None => start_pty_bridge(ble_ser_weak, baud_rate, pty_link, bridge_events),
+            #[cfg(not(target_os = "linux"))]
+            None => None,
+        };
+        if bridge.is_none() {
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+        bridge
+    } else {
+        #[cfg(not(target_os = "linux"))]
+        let _ = pty_link;
+        None
+    };
+
+    let mut rx_translator = RxTranslator::new(in_eol);
+    let hex_display = AtomicBool::new(hex_mode); // toggled in interactive mode
+    let quit = AtomicBool::new(false);
+    let ble_ser = &*ble_ser;
+    thread::scope(|s| {
+        // setting the baud rate waits for the device, so it's not done by the printing thread
+        let (baud_tx, baud_rx) = mpsc::channel::<()>();
+        let baud_tx = baud_on_connect.map(|baud| {
+            s.spawn(move || {
+                // ends when the printing thread drops the sender
+                while baud_rx.recv().is_ok() {
+                    match ble_ser.set_baud_rate(baud) {
+                        Ok(b) => status!("BleSerial: Baudrate set. expected: {baud} current: {b}"),
+                        Err(e) => status!("BleSerial: Baudrate not set: {e}"),
                     }
                 }
-                BleSerialEvent::Disconnect => {
-                    println!("BleSerial Event: Disconnected");
-                    if clear_on_disc {
-                        let _ = ble_ser.lock().unwrap().drain_read_buf();
+            });
+            baud_tx
+        });
+
+        // prints events, received data and statistics until quitting
+        s.spawn(|| {
+            let baud_tx = baud_tx; // dropped on return, ending the baud rate thread
+            let mut buf = [0u8; 4096];
+            let stats_interval = Duration::from_millis(STATS_INTERVAL_MS);
+            let mut t_stats = Instant::now() + stats_interval;
+            while !quit.load(Ordering::Relaxed) {
+                loop {
+                    match events.try_recv() {
+                        Ok(evt) => print_event(ble_ser, evt, baud_tx.as_ref(), clear_on_disc),
+                        Err(TryRecvError::Lagged(_)) => (), // mostly missed `Receive` events
+                        Err(_) => break,
                     }
                 }
-                BleSerialEvent::Receive(data) => {
-                    let drain_buf = ble_ser.lock().unwrap().drain_read_buf();
-                    assert_eq!(data, drain_buf); //because it's not read elsewhere
-                    if hex_mode {
-                        println!("BleSerial Receive: {}", &bytes_to_spaced_hex(&data));
-                    } else if let Ok(s) = String::from_utf8(data) {
-                        println!("BleSerial Receive: {}", s);
-                    } else {
-                        println!("BleSerial Receive: {}", &bytes_to_spaced_hex(&drain_buf));
+                if stats_mode && Instant::now() >= t_stats {
+                    tty::print_status(&format_stats(&ble_ser.stats()));
+                    t_stats += stats_interval;
+                }
+                if bridged {
+                    thread::sleep(Duration::from_millis(EVENT_POLL_MS));
+                    continue;
+                }
+                // returns after `EVENT_POLL_MS` if nothing is received
+                match (&mut &*ble_ser).read(&mut buf) {
+                    Ok(0) => thread::sleep(Duration::from_millis(EVENT_POLL_MS)), // gave up
+                    Ok(cnt) => {
+                        let hex_mode = hex_display.load(Ordering::Relaxed);
+                        let data = if hex_mode {
+                            buf[..cnt].to_vec()
+                        } else {
+                            rx_translator.translate(&buf[..cnt])
+                        };
+                        print_received(&data, hex_mode, raw_mode || interactive);
                     }
+                    Err(_) => (),
                 }
-                BleSerialEvent::WriteFailed(data) => {
-                    println!(
-                        "BleSerial Event: WriteFailed {}",
-                        &bytes_to_spaced_hex(&data)
-                    );
+            }
+        });
+
+        let code = if bridged {
+            wait_for_quit()
+        } else if interactive {
+            match tty::run_interactive(ble_ser, eol, &hex_display) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    println!("BleSerial: console error: {e}");
+                    ExitCode::from(EXIT_IO_ERROR)
                 }
             }
-        })
-        .unwrap();
+        } else {
+            send_lines(ble_ser, hex_mode, eol)
+        };
+        quit.store(true, Ordering::Relaxed);
+        code
+    })
+}
 
-    let mut connected = false;
+fn print_event(
+    ble_ser: &BleSerial,
+    evt: BleSerialEvent,
+    set_baud: Option<&mpsc::Sender<()>>, // asks for setting the baud rate on connection
+    clear_on_disc: bool,
+) {
+    match evt {
+        BleSerialEvent::Connect => {
+            status!("BleSerial Event: Connected");
+            if let Some(set_baud) = set_baud {
+                let _ = set_baud.send(());
+            } else if let Some(b) = ble_ser.baud_rate() {
+                status!("BleSerial: Baudrate {b}");
+            }
+        }
+        BleSerialEvent::Disconnect => {
+            status!("BleSerial Event: Disconnected");
+            if clear_on_disc {
+                let _ = ble_ser.drain_read_buf();
+            }
+        }
+        BleSerialEvent::Receive(_) => (), // the data is read from the buffer
+        BleSerialEvent::WriteFailed(data) => {
+            status!(
+                "BleSerial Event: WriteFailed {}",
+                &bytes_to_spaced_hex(&data)
+            );
+        }
+        BleSerialEvent::Error(e) => {
+            status!("BleSerial Event: Error: {e}");
+        }
+        BleSerialEvent::Reconnecting { attempt, next_in } => {
+            status!(
+                "BleSerial Event: Reconnecting (attempt {attempt}) in {:.1} s",
+                next_in.as_secs_f32()
+            );
+        }
+        BleSerialEvent::GaveUp => {
+            status!("BleSerial Event: Gave up reconnecting");
+        }
+        BleSerialEvent::AdapterUnavailable => {
+            status!("BleSerial Event: Bluetooth adapter unavailable");
+        }
+        BleSerialEvent::AdapterAvailable => {
+            status!("BleSerial Event: Bluetooth adapter available");
+        }
+    }
+}
+
+fn print_received(data: &[u8], hex_mode: bool, raw: bool) {
+    if raw {
+        let mut stdout = io::stdout().lock();
+        let _ = if hex_mode {
+            stdout.write_all(bytes_to_spaced_hex(data).as_bytes())
+        } else {
+            stdout.write_all(data)
+        };
+        let _ = stdout.flush();
+    } else if hex_mode {
+        status!("BleSerial Receive: {}", &bytes_to_spaced_hex(data));
+    } else if let Ok(s) = std::str::from_utf8(data) {
+        status!("BleSerial Receive: {}", s);
+    } else {
+        status!("BleSerial Receive: {}", &bytes_to_spaced_hex(data));
+    }
+}
+
+// in PTY or TCP mode, the console only waits for the quit command
+fn wait_for_quit() -> ExitCode {
     let mut cmd_line = String::new();
+    println!("enter 'blequit' to quit.");
+    loop {
+        match io::stdin().read_line(&mut cmd_line) {
+            Ok(0) => thread::park(), // stdin is closed, run until killed
+            Ok(_) if cmd_line.trim() == "blequit" => return ExitCode::SUCCESS,
+            Ok(_) => cmd_line.clear(),
+            Err(_) => return ExitCode::from(EXIT_IO_ERROR),
+        }
+    }
+}
+
+fn send_lines(mut ble_ser: &BleSerial, hex_mode: bool, eol: TxEol) -> ExitCode {
+    let mut cmd_line = String::new();
+    let mut connected = false;
     println!("enter data to be sent after connected; enter 'blequit' to quit.");
     loop {
         if !connected {
             thread::sleep(Duration::from_millis(50));
-            connected = ble_ser.lock().unwrap().is_connected();
+            connected = ble_ser.is_connected();
             if !connected {
                 continue;
             }
         }
-        if io::stdin().read_line(&mut cmd_line).is_err() {
-            return;
+        match io::stdin().read_line(&mut cmd_line) {
+            Ok(0) => {
+                thread::park(); // stdin is closed, run until killed
+                continue;
+            }
+            Ok(_) => (),
+            Err(_) => return ExitCode::from(EXIT_IO_ERROR),
         }
         if cmd_line.trim() == "blequit" {
-            return;
+            return ExitCode::SUCCESS;
         }
         let result = if hex_mode {
             if let Ok(vec_bytes) = Vec::from_hex(cmd_line.replace(" ", "").trim()) {
-                ble_ser.lock().unwrap().write(&vec_bytes)
+                ble_ser.write_all(&vec_bytes)
             } else {
                 println!("BleSerial: Failed to parse hex input.");
                 Err(io::Error::from(io::ErrorKind::InvalidInput))
             }
         } else {
-            // TODO: add option for CR, LF, or CR+LF
-            ble_ser.lock().unwrap().write(cmd_line.as_bytes())
+            ble_ser.write_all(&eol.apply(&cmd_line))
         };
         if let Err(e) = result {
             if e.kind() != io::ErrorKind::InvalidInput {
@@ -147,6 +454,309 @@ fn main() {
     }
 }
 
+#[cfg(target_os = "linux")]
+fn start_pty_bridge(
+    ble_ser: Weak<BleSerial>,
+    baud_rate: Option<u32>,
+    link: Option<PathBuf>,
+    events: broadcast::Receiver<BleSerialEvent>,
+) -> Option<Box<dyn Any>> {
+    match pty::PtyBridge::start(ble_ser, baud_rate, link, events) {
+        Ok(pty_bridge) => {
+            println!("BleSerial: PTY created at {}", pty_bridge.path().display());
+            Some(Box::new(pty_bridge))
+        }
+        Err(e) => {
+            println!("BleSerial: failed to create PTY: {e}");
+            None
+        }
+    }
+}
+
+fn start_tcp_bridge(
+    ble_ser: Weak<BleSerial>,
+    addr: &str,
+    rfc2217: bool,
+    events: broadcast::Receiver<BleSerialEvent>,
+) -> Option<Box<dyn Any>> {
+    match tcp::TcpBridge::start(ble_ser, addr, rfc2217, events) {
+        Ok(tcp_bridge) => {
+            let mode = if rfc2217 { "RFC 2217" } else { "raw" };
+            println!(
+                "BleSerial: listening on {} ({mode})",
+                tcp_bridge.local_addr()
+            );
+            Some(Box::new(tcp_bridge))
+        }
+        Err(e) => {
+            println!("BleSerial: failed to listen on {addr}: {e}");
+            None
+        }
+    }
+}
+
+fn scan_and_print(adapter: &AdapterSelector, timeout: Duration) -> ExitCode {
+    eprintln!("scanning for {} s...", timeout.as_secs_f32());
+    let mut bridges = match rtl8762c_ble_uart_host::scan_adapter(adapter, timeout) {
+        Ok(bridges) => bridges,
+        Err(e) => {
+            eprintln!("BleSerial: scan failed: {e}");
+            return exit_code(&e);
+        }
+    };
+    bridges.sort_by_key(|b| std::cmp::Reverse(b.rssi));
+    print!("{}", format_bridge_table(&bridges));
+    if bridges.is_empty() {
+        ExitCode::from(EXIT_NOT_FOUND)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn run_baud(conn: ConnectArgs, baud_rate: Option<u32>) -> ExitCode {
+    let ble_ser = match connect(conn) {
+        Ok(ble_ser) => ble_ser,
+        Err(code) => return code,
+    };
+    match baud_rate {
+        Some(baud) => match ble_ser.set_baud_rate(baud) {
+            Ok(b) => {
+                println!("{b}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("BleSerial: {e}");
+                exit_code(&e)
+            }
+        },
+        None => match ble_ser.baud_rate() {
+            Some(b) => {
+                println!("{b}");
+                ExitCode::SUCCESS
+            }
+            None => ExitCode::from(EXIT_IO_ERROR), // disconnected just now
+        },
+    }
+}
+
+fn run_send(
+    conn: ConnectArgs,
+    baud_rate: Option<u32>,
+    hex_mode: bool,
+    data: Option<String>,
+) -> ExitCode {
+    let data = match data {
+        Some(data) => data.into_bytes(),
+        None => {
+            let mut data = Vec::new();
+            if let Err(e) = io::stdin().read_to_end(&mut data) {
+                eprintln!("BleSerial: can't read stdin: {e}");
+                return ExitCode::from(EXIT_IO_ERROR);
+            }
+            data
+        }
+    };
+    let data = if hex_mode {
+        let hex_str: String = String::from_utf8_lossy(&data).split_whitespace().collect();
+        match Vec::from_hex(hex_str) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("BleSerial: invalid hex data: {e}");
+                return ExitCode::from(2);
+            }
+        }
+    } else {
+        data
+    };
+
+    let mut ble_ser = match connect(conn) {
+        Ok(ble_ser) => ble_ser,
+        Err(code) => return code,
+    };
+    if let Some(code) = baud_rate.and_then(|baud| set_baud_or_exit(&ble_ser, baud)) {
+        return code;
+    }
+    if let Err(e) = ble_ser.write_all(&data).and_then(|_| ble_ser.flush()) {
+        eprintln!("BleSerial: {e}");
+        return ExitCode::from(EXIT_IO_ERROR);
+    }
+    ExitCode::SUCCESS
+}
+
+fn run_monitor(
+    conn: ConnectArgs,
+    baud_rate: Option<u32>,
+    hex_mode: bool,
+    duration: Option<Duration>,
+) -> ExitCode {
+    let mut ble_ser = match connect(conn) {
+        Ok(ble_ser) => ble_ser,
+        Err(code) => return code,
+    };
+    if let Some(code) = baud_rate.and_then(|baud| set_baud_or_exit(&ble_ser, baud)) {
+        return code;
+    }
+    let t_end = duration.map(|d| Instant::now() + d);
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 4096];
+    while t_end.is_none_or(|t| Instant::now() < t) {
+        let cnt = match ble_ser.read(&mut buf) {
+            Ok(0) => {
+                // the link has given up reconnecting
+                let e = ble_ser.last_error().unwrap_or(BleSerialError::Disconnected);
+                eprintln!("BleSerial: {e}");
+                return exit_code(&e);
+            }
+            Ok(cnt) => cnt,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => {
+                eprintln!("BleSerial: {e}");
+                return ExitCode::from(EXIT_IO_ERROR);
+            }
+        };
+        let result = if hex_mode {
+            writeln!(stdout, "{}", bytes_to_spaced_hex(&buf[..cnt]).trim_end())
+        } else {
+            stdout.write_all(&buf[..cnt])
+        };
+        if result.and_then(|_| stdout.flush()).is_err() {
+            break; // stdout is closed
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+// waits for the connection for one-shot commands
+fn connect(conn: ConnectArgs) -> Result<BleSerial, ExitCode> {
+    let ble_ser = BleSerialBuilder::new(conn.link.device)
+        .adapter(conn.link.adapter)
+        .read_timeout(Duration::from_millis(READ_TIMEOUT_MS))
+        .build()
+        .map_err(|e| {
+            eprintln!("BleSerial: {e}");
+            exit_code(&e)
+        })?;
+    let t_end = Instant::now() + conn.timeout;
+    while Instant::now() < t_end {
+        if ble_ser.is_connected() {
+            return Ok(ble_ser);
+        }
+        if matches!(ble_ser.health(), Health::Stopped | Health::Panicked) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let e = ble_ser
+        .last_error()
+        .unwrap_or(BleSerialError::DeviceNotFound);
+    eprintln!("BleSerial: {e}");
+    Err(exit_code(&e))
+}
+
+fn set_baud_or_exit(ble_ser: &BleSerial, baud: u32) -> Option<ExitCode> {
+    let e = ble_ser.set_baud_rate(baud).err()?;
+    eprintln!("BleSerial: {e}");
+    Some(exit_code(&e))
+}
+
+fn exit_code(e: &BleSerialError) -> ExitCode {
+    ExitCode::from(match e {
+        BleSerialError::AdapterNotFound
+        | BleSerialError::AdapterPoweredOff
+        | BleSerialError::DeviceNotFound
+        | BleSerialError::AmbiguousDevice(_)
+        | BleSerialError::ServiceMissing
+        | BleSerialError::CharacteristicMissing(_) => EXIT_NOT_FOUND,
+        BleSerialError::BaudRejected { .. } => EXIT_BAUD_REJECTED,
+        BleSerialError::ConnectionFailed(_)
+        | BleSerialError::NotifySubscribeFailed
+        | BleSerialError::WriteFailed { .. }
+        | BleSerialError::Disconnected
+        | BleSerialError::Transport(_) => EXIT_IO_ERROR,
+        _ => 1,
+    })
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("{e}"))
+}
+
+fn format_stats(stats: &LinkStats) -> String {
+    let secs = stats.time_connected.as_secs();
+    let sizes: Vec<String> = stats
+        .notification_sizes
+        .iter()
+        .map(|(size, cnt)| format!("{size}B x{cnt}"))
+        .collect();
+    format!(
+        "BleSerial Stats: connected {:02}:{:02}:{:02}, reconnects {}\n\
+        \treceived {} bytes in {} notifications, dropped {} bytes\n\
+        \twritten {} bytes, write retries {}, write failures {}\n\
+        \tbaud rate set {} times, failed {} times\n\
+        \tnotification sizes: {}\n",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        stats.reconnects,
+        stats.bytes_received,
+        stats.notifications_received,
+        stats.bytes_dropped,
+        stats.bytes_written,
+        stats.write_retries,
+        stats.write_failures,
+        stats.baud_set_attempts,
+        stats.baud_set_failures,
+        if sizes.is_empty() {
+            "-".to_string()
+        } else {
+            sizes.join(", ")
+        }
+    )
+}
+
+fn format_bridge_table(bridges: &[DiscoveredBridge]) -> String {
+    let rows: Vec<[String; 4]> = bridges
+        .iter()
+        .map(|b| {
+            [
+                b.id.clone(),
+                b.name.clone().unwrap_or("-".to_string()),
+                b.rssi.map_or("-".to_string(), |r| format!("{r} dBm")),
+                match b.connectable {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "-",
+                }
+                .to_string(),
+            ]
+        })
+        .collect();
+    let header = ["ADDRESS", "NAME", "RSSI", "CONNECTABLE"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in rows.iter() {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, w)| format!("{cell:<w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    if rows.is_empty() {
+        table.push_str("(no bridge found)\n");
+    }
+    table
+}
+
 // TODO: optimize
 fn bytes_to_spaced_hex(bytes: &[u8]) -> String {
     let chars = bytes.encode_hex::<Vec<char>>();
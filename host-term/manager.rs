@@ -0,0 +1,367 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Multiple bridges sharing one adapter, one runtime and one scan.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use tokio::sync::{mpsc, Notify};
+use uuid::Uuid;
+
+use crate::{
+    link::{self, BleSerialRes},
+    transport::{
+        with_system_adapter, Advertisement, BoxStream, GattDevice, GattTransport, TransportError,
+        TransportResult, WithTransport,
+    },
+    AdapterSelector, BleSerial, BleSerialBuilder, BleSerialError, DeviceSelector, UUID_SERV,
+};
+
+type LinkTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+type SpawnLink = Box<dyn Fn(Arc<Mutex<BleSerialRes>>) -> LinkTask + Send + Sync>;
+
+/// Connects to multiple bridges with one bluetooth adapter. Devices discovered by
+/// a single scan are dispatched to the handles waiting for them, instead of each
+/// handle scanning on its own.
+///
+/// Handles are [`BleSerial`]s running on the manager's runtime, which is kept
+/// alive until the manager and all handles are dropped.
+pub struct BleSerialManager {
+    rt: Option<Arc<tokio::runtime::Runtime>>,
+    spawn_link: SpawnLink,
+}
+
+impl BleSerialManager {
+    /// Opens the bluetooth adapter chosen by `adapter`; fails with
+    /// `AdapterNotFound` if it's not present.
+    pub fn new(adapter: AdapterSelector) -> Result<Self, BleSerialError> {
+        let rt = new_runtime()?;
+        let spawn_link = rt
+            .block_on(with_system_adapter(&adapter, StartScan))
+            .map_err(|_| BleSerialError::AdapterNotFound)?;
+        Ok(Self {
+            rt: Some(Arc::new(rt)),
+            spawn_link,
+        })
+    }
+
+    /// Works with the given transport instead of the system's bluetooth adapter.
+    pub fn with_transport<T: GattTransport>(transport: T) -> Result<Self, BleSerialError> {
+        let rt = new_runtime()?;
+        let spawn_link = {
+            let _guard = rt.enter();
+            spawn_link_fn(transport)
+        };
+        Ok(Self {
+            rt: Some(Arc::new(rt)),
+            spawn_link,
+        })
+    }
+
+    /// Returns a handle connecting to the device chosen by `device`, with the default options.
+    pub fn open(&self, device: impl Into<DeviceSelector>) -> BleSerial {
+        self.open_with(BleSerialBuilder::new(device))
+    }
+
+    /// Returns a handle configured by `builder`; its adapter and worker threads are not used.
+    pub fn open_with(&self, builder: BleSerialBuilder) -> BleSerial {
+        let rt = self.rt.clone().expect("the runtime is taken on drop");
+        builder.build_in(rt, |res| (self.spawn_link)(res))
+    }
+
+    /// Writes `data` to every handle in `group`. The data is queued for all of them
+    /// before waiting, so that the bridges receive it at about the same time; the
+    /// write queue limit is not applied. Returns the result of each handle in order,
+    /// as `flush()` would.
+    pub fn broadcast<'a>(
+        &self,
+        group: impl IntoIterator<Item = &'a BleSerial>,
+        data: &[u8],
+    ) -> Vec<Result<(), BleSerialError>> {
+        let group: Vec<&BleSerial> = group.into_iter().collect();
+        let queued: Vec<Result<(), BleSerialError>> = group
+            .iter()
+            .map(|ble_ser| ble_ser.queue_broadcast(data))
+            .collect();
+        group
+            .iter()
+            .zip(queued)
+            .map(|(ble_ser, queued)| queued.and_then(|_| ble_ser.wait_flushed()))
+            .collect()
+    }
+}
+
+impl Drop for BleSerialManager {
+    fn drop(&mut self) {
+        // the runtime is kept by the handles that are still alive
+        if let Some(rt) = self.rt.take().and_then(Arc::into_inner) {
+            rt.shutdown_timeout(Duration::from_millis(2000));
+        }
+    }
+}
+
+// starts the shared scan; must be called inside the runtime
+fn spawn_link_fn<T: GattTransport>(transport: T) -> SpawnLink {
+    let shared = SharedScan::start(transport);
+    Box::new(move |res| {
+        Box::pin(link::run_task(
+            res.clone(),
+            link::ble_loop(shared.clone(), res),
+        ))
+    })
+}
+
+// starts the shared scan with the system's adapter
+struct StartScan;
+
+impl WithTransport for StartScan {
+    type Output = SpawnLink;
+
+    async fn run<T: GattTransport>(self, transport: T) -> SpawnLink {
+        spawn_link_fn(transport)
+    }
+}
+
+fn new_runtime() -> Result<tokio::runtime::Runtime, BleSerialError> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .map_err(|_| BleSerialError::Runtime)
+}
+
+struct ScanState<D> {
+    next_id: u64,
+    waiters: Vec<(u64, mpsc::UnboundedSender<D>)>,
+    found: Vec<D>,            // devices discovered by the current scan
+    claimed: HashSet<String>, // devices being connected or connected by the handles
+}
+
+struct SharedScanInner<T: GattTransport> {
+    transport: T,
+    state: Mutex<ScanState<T::Device>>,
+    notify: Notify, // waiters added or removed
+}
+
+impl<T: GattTransport> SharedScanInner<T> {
+    fn lock_state(&self) -> MutexGuard<'_, ScanState<T::Device>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Transport of the handles, whose `discover_devices()` takes devices from the shared scan.
+struct SharedScan<T: GattTransport>(Arc<SharedScanInner<T>>);
+
+impl<T: GattTransport> Clone for SharedScan<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: GattTransport> SharedScan<T> {
+    fn start(transport: T) -> Self {
+        let inner = Arc::new(SharedScanInner {
+            transport,
+            state: Mutex::new(ScanState {
+                next_id: 0,
+                waiters: Vec::new(),
+                found: Vec::new(),
+                claimed: HashSet::new(),
+            }),
+            notify: Notify::new(),
+        });
+        tokio::spawn(run_scan(inner.clone()));
+        Self(inner)
+    }
+}
+
+// scans while any handle is waiting for a device
+async fn run_scan<T: GattTransport>(inner: Arc<SharedScanInner<T>>) {
+    loop {
+        while inner.lock_state().waiters.is_empty() {
+            inner.notify.notified().await;
+        }
+        let mut discoverer = match inner.transport.discover_devices(&[UUID_SERV]).await {
+            Ok(discoverer) => discoverer,
+            Err(_) => {
+                debug!("run_scan(): discover_devices failed.");
+                tokio::time::sleep(Duration::from_millis(1000)).await;
+                continue;
+            }
+        };
+        debug!("run_scan(): started discovering.");
+        loop {
+            tokio::select! {
+                item = discoverer.next() => match item {
+                    Some(Ok(dev)) => {
+                        let mut state = inner.lock_state();
+                        let id = dev.id();
+                        if !state.found.iter().any(|d| d.id() == id) {
+                            state.found.push(dev.clone());
+                        }
+                        state.waiters.retain(|(_, tx)| tx.send(dev.clone()).is_ok());
+                    }
+                    Some(Err(_)) => (),
+                    None => break,
+                },
+                _ = inner.notify.notified() => {
+                    if inner.lock_state().waiters.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
+        drop(discoverer);
+        debug!("run_scan(): stopped discovering.");
+        inner.lock_state().found.clear();
+        // avoids busy looping if the discovery stream ends immediately
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+// unregisters the waiter when its discovery stream is dropped
+struct Waiter<T: GattTransport> {
+    inner: Arc<SharedScanInner<T>>,
+    id: u64,
+}
+
+impl<T: GattTransport> Drop for Waiter<T> {
+    fn drop(&mut self) {
+        self.inner
+            .lock_state()
+            .waiters
+            .retain(|(id, _)| *id != self.id);
+        self.inner.notify.notify_one();
+    }
+}
+
+impl<T: GattTransport> GattTransport for SharedScan<T> {
+    type Device = T::Device;
+
+    async fn wait_available(&self) -> TransportResult<()> {
+        self.0.transport.wait_available().await
+    }
+
+    async fn adapter_events(&self) -> TransportResult<BoxStream<'_, bool>> {
+        self.0.transport.adapter_events().await
+    }
+
+    async fn discover_devices<'a>(
+        &'a self,
+        _services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, TransportResult<T::Device>>> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let id = {
+            let mut state = self.0.lock_state();
+            for dev in state.found.iter() {
+                let _ = tx.send(dev.clone());
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push((id, tx));
+            id
+        };
+        self.0.notify.notify_one();
+        let waiter = Waiter {
+            inner: self.0.clone(),
+            id,
+        };
+        Ok(Box::pin(async_stream::stream! {
+            let waiter = waiter;
+            while let Some(dev) = rx.recv().await {
+                // skips devices used by other handles
+                if waiter.inner.lock_state().claimed.contains(&dev.id()) {
+                    continue;
+                }
+                yield Ok(dev);
+            }
+        }))
+    }
+
+    async fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, Advertisement<T::Device>>> {
+        self.0.transport.scan(services).await
+    }
+
+    async fn connect_device(&self, device: &T::Device) -> TransportResult<()> {
+        let id = device.id();
+        if !self.0.lock_state().claimed.insert(id.clone()) {
+            return Err(TransportError::new("used by another handle"));
+        }
+        let mut claim = Claim {
+            inner: &self.0,
+            id,
+            kept: false,
+        };
+        self.0.transport.connect_device(device).await?;
+        claim.kept = true;
+        Ok(())
+    }
+
+    fn release_device(&self, device: &T::Device) {
+        self.0.lock_state().claimed.remove(&device.id());
+        self.0.transport.release_device(device);
+    }
+}
+
+// releases the claim if connecting fails or is cancelled
+struct Claim<'a, T: GattTransport> {
+    inner: &'a SharedScanInner<T>,
+    id: String,
+    kept: bool,
+}
+
+impl<T: GattTransport> Drop for Claim<'_, T> {
+    fn drop(&mut self) {
+        if !self.kept {
+            self.inner.lock_state().claimed.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{
+        fake::{wait_until, FakePeripheral, FakeTransport},
+        BleSerialManager, DeviceSelector,
+    };
+
+    #[test]
+    fn shared_scan_and_broadcast() {
+        let fake_a = FakePeripheral::new("00:E0:02:00:00:0A", "RTL-UART-00000A");
+        let fake_b = FakePeripheral::new("00:E0:02:00:00:0B", "RTL-UART-00000B");
+        let transport = FakeTransport::new(&[fake_a.clone(), fake_b.clone()]);
+        let manager = BleSerialManager::with_transport(transport).unwrap();
+        let ser_b = manager.open(DeviceSelector::MacSuffix("0B".to_string()));
+        let ser_a = manager.open("00:E0:02:00:00:0A");
+        assert!(wait_until(|| ser_a.is_connected() && ser_b.is_connected()));
+        assert_eq!(ser_a.device_name().as_deref(), Some("RTL-UART-00000A"));
+        assert_eq!(ser_b.device_name().as_deref(), Some("RTL-UART-00000B"));
+
+        // both devices are claimed
+        let ser_c = manager.open(DeviceSelector::FirstFound);
+        thread::sleep(Duration::from_millis(500));
+        assert!(!ser_c.is_connected());
+
+        let results = manager.broadcast([&ser_a, &ser_b], b"hello");
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(fake_a.take_uart_tx(), b"hello");
+        assert_eq!(fake_b.take_uart_tx(), b"hello");
+
+        // the claim is released when the handle is dropped
+        drop(ser_a);
+        assert!(wait_until(|| ser_c.is_connected()));
+        assert_eq!(ser_c.device_name().as_deref(), Some("RTL-UART-00000A"));
+    }
+}
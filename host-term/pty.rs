@@ -0,0 +1,232 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Pseudo-terminal bridge of `--pty`, for programs that only work with a tty (Linux only).
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::fd::{AsFd, AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::Weak,
+    thread,
+};
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    libc,
+    poll::{poll, PollFd, PollFlags},
+    pty::openpty,
+    sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+    unistd::ttyname,
+};
+
+use rtl8762c_ble_uart_host::{BleSerial, BleSerialEvent};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const POLL_INTERVAL_MS: u16 = 100;
+
+// `termios2` keeps the baud rate as a number, so that any baud rate set by
+// the program on the other side (with `BOTHER`) can be forwarded.
+nix::ioctl_read_bad!(tcgets2, libc::TCGETS2, libc::termios2);
+nix::ioctl_write_ptr_bad!(tcsets2, libc::TCSETS2, libc::termios2);
+
+/// Keeps the symlink to the pseudo-terminal; the threads stop when `BleSerial` is dropped.
+pub struct PtyBridge {
+    path: PathBuf,
+    link: Option<PathBuf>,
+}
+
+impl PtyBridge {
+    /// Creates the pseudo-terminal and starts shuttling data in both directions.
+    /// `events` is a receiver got from `BleSerial::subscribe()`.
+    /// `baud_rate` is set on each connection until it's changed on the pseudo-terminal.
+    pub fn start(
+        ble_ser: Weak<BleSerial>,
+        baud_rate: Option<u32>,
+        link: Option<PathBuf>,
+        events: broadcast::Receiver<BleSerialEvent>,
+    ) -> io::Result<Self> {
+        let pty = openpty(None, None)?;
+        let mut termios = tcgetattr(&pty.slave)?;
+        cfmakeraw(&mut termios);
+        tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+        if let Some(baud) = baud_rate {
+            set_speed(&pty.slave, baud)?;
+        }
+        fcntl(pty.master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        let path = ttyname(&pty.slave)?;
+
+        if let Some(link) = &link {
+            // replaces a stale symlink, but never a regular file
+            if fs::symlink_metadata(link).is_ok_and(|m| m.file_type().is_symlink()) {
+                fs::remove_file(link)?;
+            }
+            std::os::unix::fs::symlink(&path, link)?;
+        }
+
+        let master = File::from(pty.master);
+        let master_write = master.try_clone()?;
+        let ble_ser_weak = ble_ser.clone();
+        thread::spawn(move || ble_to_pty(ble_ser_weak, master_write, events));
+        thread::spawn(move || pty_to_ble(ble_ser, master, pty.slave, baud_rate));
+
+        Ok(Self { path, link })
+    }
+
+    /// Path of the pseudo-terminal, like `/dev/pts/3`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PtyBridge {
+    fn drop(&mut self) {
+        if let Some(link) = &self.link {
+            if fs::read_link(link).is_ok_and(|p| p == self.path) {
+                let _ = fs::remove_file(link);
+            }
+        }
+    }
+}
+
+fn ble_to_pty(
+    ble_ser: Weak<BleSerial>,
+    mut master: File,
+    mut events: broadcast::Receiver<BleSerialEvent>,
+) {
+    loop {
+        match events.blocking_recv() {
+            Ok(BleSerialEvent::Receive(_)) | Err(RecvError::Lagged(_)) => (),
+            Ok(_) => continue,
+            Err(RecvError::Closed) => return,
+        }
+        let Some(ble_ser) = ble_ser.upgrade() else {
+            return;
+        };
+        let data = ble_ser.drain_read_buf();
+        drop(ble_ser);
+        // like an UART, data is lost if the other side doesn't read it in time
+        let mut data = &data[..];
+        while !data.is_empty() {
+            match master.write(data) {
+                Ok(cnt) => data = &data[cnt..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+fn pty_to_ble(
+    ble_ser: Weak<BleSerial>,
+    mut master: File,
+    slave: OwnedFd, // kept open, so that the master doesn't fail when no one opens the slave
+    mut baud_rate: Option<u32>,
+) {
+    let mut buf = [0u8; 4096];
+    let mut last_speed = get_speed(&slave).ok();
+    let mut connected = false;
+    loop {
+        let mut fds = [PollFd::new(master.as_fd(), PollFlags::POLLIN)];
+        let readable = poll(&mut fds, POLL_INTERVAL_MS).is_ok_and(|cnt| cnt > 0);
+        let Some(ble_ser) = ble_ser.upgrade() else {
+            return;
+        };
+
+        if readable {
+            match master.read(&mut buf) {
+                Ok(cnt) if cnt > 0 => {
+                    if let Err(e) = (&*ble_ser).write_all(&buf[..cnt]) {
+                        println!("BleSerial: PTY data not sent: {e}");
+                    }
+                }
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => {
+                    println!("BleSerial: PTY read failed: {e}");
+                    return;
+                }
+                _ => (),
+            }
+        }
+
+        // forwards the baud rate set on the pseudo-terminal
+        let speed = get_speed(&slave).ok();
+        let mut apply = speed != last_speed;
+        if apply {
+            last_speed = speed;
+            baud_rate = speed;
+        }
+        let now_connected = ble_ser.is_connected();
+        apply |= now_connected && !connected;
+        connected = now_connected;
+        if let (true, true, Some(baud)) = (apply, connected, baud_rate) {
+            match ble_ser.set_baud_rate(baud) {
+                Ok(b) => println!("BleSerial: PTY baud rate {baud}, current: {b}"),
+                Err(e) => println!("BleSerial: PTY baud rate {baud} not set: {e}"),
+            }
+        }
+    }
+}
+
+fn get_speed(fd: &OwnedFd) -> nix::Result<u32> {
+    // SAFETY: `termios2` is plain data filled by the kernel.
+    let mut t: libc::termios2 = unsafe { std::mem::zeroed() };
+    unsafe { tcgets2(fd.as_raw_fd(), &mut t) }?;
+    Ok(t.c_ospeed)
+}
+
+fn set_speed(fd: &OwnedFd, baud: u32) -> nix::Result<()> {
+    // SAFETY: `termios2` is plain data filled by the kernel.
+    let mut t: libc::termios2 = unsafe { std::mem::zeroed() };
+    unsafe { tcgets2(fd.as_raw_fd(), &mut t) }?;
+    t.c_cflag &= !libc::CBAUD;
+    t.c_cflag |= libc::BOTHER;
+    t.c_ispeed = baud;
+    t.c_ospeed = baud;
+    unsafe { tcsets2(fd.as_raw_fd(), &t) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use rtl8762c_ble_uart_host::{
+        fake::{wait_until, FakePeripheral},
+        DeviceSelector,
+    };
+
+    use super::*;
+
+    #[test]
+    fn forward_data_and_baud_rate() {
+        let fake = FakePeripheral::default();
+        let ble_ser = Arc::new(
+            BleSerial::build_with_transport(
+                fake.transport(),
+                DeviceSelector::FirstFound,
+                Duration::from_secs(1),
+            )
+            .unwrap(),
+        );
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let bridge = PtyBridge::start(
+            Arc::downgrade(&ble_ser),
+            None,
+            None,
+            ble_ser.subscribe().unwrap(),
+        )
+        .unwrap();
+
+        let mut slave = File::options().write(true).open(bridge.path()).unwrap();
+        slave.write_all(b"hello").unwrap();
+        let mut received = Vec::new();
+        assert!(wait_until(|| {
+            received.extend(fake.take_uart_tx());
+            received == b"hello"
+        }));
+
+        set_speed(&OwnedFd::from(slave), 115200).unwrap();
+        assert!(wait_until(|| fake.baud_rate() == 115090));
+    }
+}
@@ -0,0 +1,70 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Decides whether and when to retry after a failed connection attempt or a lost
+/// connection. Attempts are counted from 1 and reset after each successful connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectPolicy {
+    /// Give up as soon as the connection fails or is lost.
+    Never,
+    /// Retry after the same delay each time.
+    Fixed {
+        delay: Duration,
+        max_attempts: Option<u32>,
+    },
+    /// Double the delay after each attempt up to `max`, with a random part of
+    /// up to a half of the delay to avoid retrying in lockstep.
+    ExponentialBackoff {
+        initial: Duration,
+        max: Duration,
+        max_attempts: Option<u32>,
+    },
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::Fixed {
+            delay: Duration::from_millis(1500),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the given attempt, or `None` for giving up.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        match self {
+            Self::Never => None,
+            Self::Fixed {
+                delay,
+                max_attempts,
+            } => (!max_attempts.is_some_and(|m| attempt > m)).then_some(*delay),
+            Self::ExponentialBackoff {
+                initial,
+                max,
+                max_attempts,
+            } => {
+                if max_attempts.is_some_and(|m| attempt > m) {
+                    return None;
+                }
+                let exp = attempt.saturating_sub(1).min(31);
+                let delay = initial.saturating_mul(1 << exp).min(*max);
+                let half = delay / 2;
+                Some(half + half.mul_f64(random_fraction()))
+            }
+        }
+    }
+}
+
+// in range [0, 1); not for cryptographic use
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
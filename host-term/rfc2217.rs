@@ -0,0 +1,357 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Telnet COM port control (RFC 2217) of `--tcp-listen --rfc2217`.
+//! Only the baud rate can be changed; other settings are reported as 8N1
+//! without flow control, which is what the bridge's UART uses.
+
+use rtl8762c_ble_uart_host::{BleSerial, BleSerialError};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const OPT_BINARY: u8 = 0;
+const OPT_SGA: u8 = 3; // suppress go ahead
+const OPT_COM_PORT: u8 = 44;
+
+const LOCAL_OPTIONS: [u8; 2] = [OPT_BINARY, OPT_SGA];
+const REMOTE_OPTIONS: [u8; 3] = [OPT_BINARY, OPT_SGA, OPT_COM_PORT];
+
+// client to server; the server responds with the code plus 100
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+const SERVER_SIGNATURE: &[u8] = b"rtl8762c-bleser";
+
+/// Serial port controlled by the client.
+pub trait ComPort {
+    /// Sets the baud rate and returns the actual one; 0 is for querying it.
+    fn set_baud(&self, baud: u32) -> u32;
+
+    /// Discards the received data that is not yet sent to the client.
+    fn purge_received(&self);
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Data,
+    Iac,
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+/// Telnet session state of one client.
+pub struct Rfc2217 {
+    state: State,
+    sub: Vec<u8>,
+    local: Vec<u8>,  // options enabled on this side
+    remote: Vec<u8>, // options enabled on the client side
+}
+
+impl Rfc2217 {
+    pub fn new() -> Self {
+        Self {
+            state: State::Data,
+            sub: Vec::new(),
+            local: LOCAL_OPTIONS.to_vec(),
+            remote: REMOTE_OPTIONS.to_vec(),
+        }
+    }
+
+    /// Options requested by the server at the beginning of the session.
+    pub fn greeting(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &opt in &self.local {
+            out.extend([IAC, WILL, opt]);
+        }
+        for &opt in &self.remote {
+            out.extend([IAC, DO, opt]);
+        }
+        out
+    }
+
+    /// Separates data from telnet commands received from the client; returns
+    /// the data to be sent to the device, and the reply to the client.
+    pub fn feed(&mut self, input: &[u8], port: &impl ComPort) -> (Vec<u8>, Vec<u8>) {
+        let (mut data, mut reply) = (Vec::new(), Vec::new());
+        for &b in input {
+            self.state = match (self.state, b) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(b);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiate(b),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Sub
+                }
+                (State::Iac, _) => State::Data, // NOP, break, etc.
+                (State::Negotiate(cmd), opt) => {
+                    self.negotiate(cmd, opt, &mut reply);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => {
+                    self.sub.push(b);
+                    State::Sub
+                }
+                (State::SubIac, IAC) => {
+                    self.sub.push(IAC);
+                    State::Sub
+                }
+                (State::SubIac, SE) => {
+                    self.subnegotiate(port, &mut reply);
+                    State::Data
+                }
+                (State::SubIac, _) => State::Data, // malformed, dropped
+            };
+        }
+        (data, reply)
+    }
+
+    fn negotiate(&mut self, cmd: u8, opt: u8, reply: &mut Vec<u8>) {
+        // replies only when the state changes, to avoid negotiation loops
+        match cmd {
+            WILL if !REMOTE_OPTIONS.contains(&opt) => reply.extend([IAC, DONT, opt]),
+            WILL if !self.remote.contains(&opt) => {
+                self.remote.push(opt);
+                reply.extend([IAC, DO, opt]);
+            }
+            DO if !LOCAL_OPTIONS.contains(&opt) => reply.extend([IAC, WONT, opt]),
+            DO if !self.local.contains(&opt) => {
+                self.local.push(opt);
+                reply.extend([IAC, WILL, opt]);
+            }
+            WONT if self.remote.contains(&opt) => {
+                self.remote.retain(|&o| o != opt);
+                reply.extend([IAC, DONT, opt]);
+            }
+            DONT if self.local.contains(&opt) => {
+                self.local.retain(|&o| o != opt);
+                reply.extend([IAC, WONT, opt]);
+            }
+            _ => (),
+        }
+    }
+
+    fn subnegotiate(&mut self, port: &impl ComPort, reply: &mut Vec<u8>) {
+        let [OPT_COM_PORT, cmd, ref payload @ ..] = self.sub[..] else {
+            return;
+        };
+        let value = payload.first().copied().unwrap_or(0);
+        let response = match cmd {
+            SIGNATURE if payload.is_empty() => SERVER_SIGNATURE.to_vec(),
+            SET_BAUDRATE => {
+                let Ok(requested) = <[u8; 4]>::try_from(payload) else {
+                    return;
+                };
+                port.set_baud(u32::from_be_bytes(requested))
+                    .to_be_bytes()
+                    .to_vec()
+            }
+            SET_DATASIZE => vec![8],
+            SET_PARITY => vec![1],   // none
+            SET_STOPSIZE => vec![1], // 1 stop bit
+            SET_CONTROL => vec![match value {
+                0..=3 | 17 | 19 => 1, // no outbound flow control
+                13..=16 | 18 => 14,   // no inbound flow control
+                4..=6 => 6,           // break off
+                7 => 8,               // DTR on
+                10 => 11,             // RTS on
+                v => v,
+            }],
+            SET_LINESTATE_MASK | SET_MODEMSTATE_MASK => vec![value],
+            PURGE_DATA => {
+                if value == 1 || value == 3 {
+                    port.purge_received();
+                }
+                vec![value]
+            }
+            _ => return,
+        };
+        reply.extend([IAC, SB, OPT_COM_PORT, cmd + SERVER_OFFSET]);
+        reply.extend(escape(&response));
+        reply.extend([IAC, SE]);
+    }
+}
+
+/// Doubles `IAC` bytes in data sent to the client.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &b in data {
+        out.push(b);
+        if b == IAC {
+            out.push(IAC);
+        }
+    }
+    out
+}
+
+impl ComPort for BleSerial {
+    fn set_baud(&self, baud: u32) -> u32 {
+        if baud == 0 {
+            return self.baud_rate().unwrap_or(0);
+        }
+        let actual = match self.set_baud_rate(baud) {
+            Ok(b) | Err(BleSerialError::BaudRejected { actual: b, .. }) => b,
+            Err(_) => self.baud_rate().unwrap_or(0),
+        };
+        println!("BleSerial: RFC 2217 baud rate {baud}, current: {actual}");
+        actual
+    }
+
+    fn purge_received(&self) {
+        self.drain_read_buf();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    // accepts any baud rate but 115200, which becomes 115090 like on the bridge
+    struct FakePort {
+        baud: Cell<u32>,
+        purged: Cell<bool>,
+    }
+
+    impl FakePort {
+        fn new() -> Self {
+            Self {
+                baud: Cell::new(9615),
+                purged: Cell::new(false),
+            }
+        }
+    }
+
+    impl ComPort for FakePort {
+        fn set_baud(&self, baud: u32) -> u32 {
+            if baud != 0 {
+                self.baud.set(if baud == 115200 { 115090 } else { baud });
+            }
+            self.baud.get()
+        }
+
+        fn purge_received(&self) {
+            self.purged.set(true);
+        }
+    }
+
+    #[test]
+    fn data_with_iac() {
+        let mut session = Rfc2217::new();
+        let (data, reply) = session.feed(&[b'a', IAC, IAC, b'b', IAC, 241], &FakePort::new());
+        assert_eq!(data, [b'a', IAC, b'b']); // IAC NOP is dropped
+        assert!(reply.is_empty());
+    }
+
+    #[test]
+    fn set_baudrate() {
+        let (mut session, port) = (Rfc2217::new(), FakePort::new());
+        let input = [IAC, SB, OPT_COM_PORT, SET_BAUDRATE, 0, 1, 0xc2, 0, IAC, SE];
+        let (data, reply) = session.feed(&input, &port);
+        assert!(data.is_empty());
+        // 115090
+        let expected = [IAC, SB, OPT_COM_PORT, 101, 0, 1, 0xc1, 0x92, IAC, SE];
+        assert_eq!(reply, expected);
+        assert_eq!(port.baud.get(), 115090);
+
+        // query
+        let input = [IAC, SB, OPT_COM_PORT, SET_BAUDRATE, 0, 0, 0, 0, IAC, SE];
+        assert_eq!(session.feed(&input, &port).1, expected);
+    }
+
+    #[test]
+    fn iac_in_subnegotiation() {
+        let (mut session, port) = (Rfc2217::new(), FakePort::new());
+        // 0x0001ff00, split between two reads
+        let input = [
+            IAC,
+            SB,
+            OPT_COM_PORT,
+            SET_BAUDRATE,
+            0,
+            1,
+            IAC,
+            IAC,
+            0,
+            IAC,
+            SE,
+        ];
+        let (data, reply) = session.feed(&input[..7], &port);
+        assert!(data.is_empty() && reply.is_empty());
+        let (data, reply) = session.feed(&input[7..], &port);
+        assert!(data.is_empty());
+        assert_eq!(
+            reply,
+            [IAC, SB, OPT_COM_PORT, 101, 0, 1, IAC, IAC, 0, IAC, SE]
+        );
+        assert_eq!(port.baud.get(), 0x0001ff00);
+    }
+
+    #[test]
+    fn purge_data() {
+        let (mut session, port) = (Rfc2217::new(), FakePort::new());
+        let input = [IAC, SB, OPT_COM_PORT, PURGE_DATA, 1, IAC, SE];
+        let (_, reply) = session.feed(&input, &port);
+        assert_eq!(reply, [IAC, SB, OPT_COM_PORT, 112, 1, IAC, SE]);
+        assert!(port.purged.get());
+    }
+
+    #[test]
+    fn negotiation_without_loops() {
+        let (mut session, port) = (Rfc2217::new(), FakePort::new());
+        // already enabled by the greeting
+        let input = [
+            IAC,
+            WILL,
+            OPT_COM_PORT,
+            IAC,
+            DO,
+            OPT_BINARY,
+            IAC,
+            DO,
+            OPT_SGA,
+        ];
+        assert!(session.feed(&input, &port).1.is_empty());
+        // not supported: echo
+        assert_eq!(session.feed(&[IAC, DO, 1], &port).1, [IAC, WONT, 1]);
+        assert_eq!(session.feed(&[IAC, WILL, 1], &port).1, [IAC, DONT, 1]);
+        // disabled and enabled again
+        assert_eq!(
+            session.feed(&[IAC, WONT, OPT_BINARY], &port).1,
+            [IAC, DONT, OPT_BINARY]
+        );
+        assert!(session.feed(&[IAC, WONT, OPT_BINARY], &port).1.is_empty());
+        assert_eq!(
+            session.feed(&[IAC, WILL, OPT_BINARY], &port).1,
+            [IAC, DO, OPT_BINARY]
+        );
+    }
+
+    #[test]
+    fn escape_iac() {
+        assert_eq!(escape(&[1, IAC, 2, IAC]), [1, IAC, IAC, 2, IAC, IAC]);
+    }
+}
@@ -0,0 +1,128 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Port of the firmware's UART baud rate calculator `rtlbaud.h`, giving the same
+//! results, so that the actual baud rate can be known before setting it.
+
+const T_CLK: f64 = 1.0 / (20.0 * 1000.0 * 1000.0);
+
+const ADJ_TABLE: [u16; 9] = [
+    0x000, 0x010, 0x022, 0x052, 0x0aa, 0x155, 0x16d, 0x1bb, 0x1f7,
+];
+
+/// UART register values for a baud rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtlBaudConfig {
+    pub div: u16,
+    pub ovsr: u16,
+    pub ovsr_adj: u16,
+    pub baud_actual: u32,
+}
+
+/// `rtl_baud_calc()`: finds the configuration with the largest OVSR and an error
+/// within `max_err_percent`.
+pub fn calc(baud_target: u32, max_err_percent: f64) -> Option<RtlBaudConfig> {
+    if baud_target < 50 {
+        return None;
+    }
+    let t_baud_target = 1.0 / baud_target as f64;
+
+    for ovsr in (1..=15u16).rev() {
+        let ovsr_actual = 0.5 * ovsr as f64 + 2.5;
+        let div_tmp = (t_baud_target / ovsr_actual) / T_CLK;
+        if div_tmp.floor() > u16::MAX as f64 {
+            continue;
+        }
+
+        let div = div_tmp.floor() as u16;
+        let t_clk_divided = T_CLK * div as f64;
+        let t_baud_err = t_baud_target - t_clk_divided * ovsr_actual;
+        let t_adj_unit = t_clk_divided / 2.0 / 9.0;
+        let adj_bits = (t_baud_err / t_adj_unit).round() as u16;
+        if adj_bits > 8 {
+            continue;
+        }
+
+        let t_baud_actual = t_clk_divided * ovsr_actual + t_adj_unit * adj_bits as f64;
+        let err_percent = 100.0 * (t_baud_actual - t_baud_target).abs() / t_baud_target;
+        if err_percent > max_err_percent {
+            continue;
+        }
+
+        return Some(RtlBaudConfig {
+            div,
+            ovsr,
+            ovsr_adj: ADJ_TABLE[adj_bits as usize],
+            baud_actual: (1.0 / t_baud_actual).round() as u32,
+        });
+    }
+    None
+}
+
+/// `rtl_baud_auto_calc()` used by the firmware: tries the error limit from 1% to 5%
+/// in steps of 0.5%. `None` means the baud rate is rejected by the firmware.
+pub fn auto_calc(baud: u32) -> Option<RtlBaudConfig> {
+    let mut err_per = 1.0;
+    while err_per <= 5.0 {
+        if let Some(conf) = calc(baud, err_per) {
+            return Some(conf);
+        }
+        err_per += 0.5;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (baud, div, ovsr, ovsr_adj, actual), printed by `rtlbaud_test.c` of the firmware
+    const VECTORS: &[(u32, u16, u16, u16, u32)] = &[
+        (50, 40000, 15, 0x000, 50),
+        (110, 18181, 15, 0x000, 110),
+        (300, 6666, 15, 0x000, 300),
+        (1200, 1666, 15, 0x000, 1200),
+        (2400, 833, 15, 0x000, 2401),
+        (4800, 416, 15, 0x000, 4808),
+        (9600, 208, 15, 0x000, 9615),
+        (14400, 138, 15, 0x010, 14413),
+        (19200, 104, 15, 0x000, 19231),
+        (38400, 52, 15, 0x000, 38462),
+        (57600, 34, 15, 0x0aa, 57545),
+        (74880, 26, 15, 0x155, 74844),
+        (115200, 17, 15, 0x0aa, 115090),
+        (128000, 15, 15, 0x1bb, 128342),
+        (230400, 9, 14, 0x052, 229885),
+        (250000, 8, 15, 0x000, 250000),
+        (256000, 8, 14, 0x155, 255682),
+        (460800, 5, 12, 0x052, 461538),
+        (500000, 4, 15, 0x000, 500000),
+        (921600, 3, 9, 0x0aa, 923077),
+        (1000000, 2, 15, 0x000, 1000000),
+        (1500000, 2, 8, 0x052, 1500000),
+        (2000000, 1, 15, 0x000, 2000000),
+        (3000000, 1, 8, 0x052, 3000000),
+        (4000000, 1, 5, 0x000, 4000000),
+        (6000000, 1, 1, 0x16d, 6000000),
+    ];
+
+    #[test]
+    fn matches_firmware() {
+        for &(baud, div, ovsr, ovsr_adj, baud_actual) in VECTORS {
+            let expected = RtlBaudConfig {
+                div,
+                ovsr,
+                ovsr_adj,
+                baud_actual,
+            };
+            assert_eq!(auto_calc(baud), Some(expected), "baud rate {baud}");
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        for baud in [0, 49, 10_000_000, 20_000_000, u32::MAX] {
+            assert_eq!(auto_calc(baud), None, "baud rate {baud}");
+        }
+    }
+}
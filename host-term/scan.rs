@@ -0,0 +1,90 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use crate::{
+    transport::{with_system_adapter, BoxStream, GattDevice, GattTransport, WithTransport},
+    AdapterSelector, BleSerialError, UUID_SERV,
+};
+
+/// RTL-UART bridge found by scanning for advertisements of service 0xA00A.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredBridge {
+    /// Platform-dependent device id: MAC address on Linux and Windows, UUID on macOS.
+    pub id: String,
+    /// Advertised name, `RTL-UART-XXXXXX` for the firmware.
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    /// `None` if it's unknown, e.g. with BlueZ.
+    pub connectable: Option<bool>,
+}
+
+/// Scans for bridges with the default bluetooth adapter, blocking for `timeout`.
+pub fn scan(timeout: Duration) -> Result<Vec<DiscoveredBridge>, BleSerialError> {
+    scan_adapter(&AdapterSelector::Default, timeout)
+}
+
+/// Scans for bridges with the chosen bluetooth adapter, blocking for `timeout`.
+pub fn scan_adapter(
+    adapter: &AdapterSelector,
+    timeout: Duration,
+) -> Result<Vec<DiscoveredBridge>, BleSerialError> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|_| BleSerialError::Runtime)?;
+    rt.block_on(with_system_adapter(adapter, ScanFor(timeout)))
+        .unwrap_or(Err(BleSerialError::AdapterNotFound))
+}
+
+// scans with the system's adapter for the given time
+struct ScanFor(Duration);
+
+impl WithTransport for ScanFor {
+    type Output = Result<Vec<DiscoveredBridge>, BleSerialError>;
+
+    async fn run<T: GattTransport>(self, transport: T) -> Self::Output {
+        scan_with_transport(&transport, self.0).await
+    }
+}
+
+/// Scans for bridges for `timeout`. Each bridge is listed once, with the latest
+/// advertisement received from it.
+pub async fn scan_with_transport<T: GattTransport>(
+    transport: &T,
+    timeout: Duration,
+) -> Result<Vec<DiscoveredBridge>, BleSerialError> {
+    let mut bridges: Vec<DiscoveredBridge> = Vec::new();
+    let mut stream = scan_stream(transport).await?;
+    let _ = tokio::time::timeout(timeout, async {
+        while let Some(bridge) = stream.next().await {
+            if let Some(prev) = bridges.iter_mut().find(|b| b.id == bridge.id) {
+                // the name may be absent in some of the packets
+                let name = bridge.name.or(prev.name.take());
+                *prev = DiscoveredBridge { name, ..bridge };
+            } else {
+                bridges.push(bridge);
+            }
+        }
+    })
+    .await;
+    Ok(bridges)
+}
+
+/// Returns a stream of bridges, yielding an item for each received advertisement.
+/// Scanning is stopped when the stream is dropped.
+pub async fn scan_stream<T: GattTransport>(
+    transport: &T,
+) -> Result<BoxStream<'_, DiscoveredBridge>, BleSerialError> {
+    transport.wait_available().await?;
+    let stream = transport.scan(&[UUID_SERV]).await?;
+    Ok(Box::pin(stream.map(|adv| DiscoveredBridge {
+        id: adv.device.id(),
+        name: adv.local_name,
+        rssi: adv.rssi,
+        connectable: adv.is_connectable,
+    })))
+}
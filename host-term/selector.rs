@@ -0,0 +1,196 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{convert::Infallible, fmt, str::FromStr};
+
+const NAME_PREFIX_FIRMWARE: &str = "RTL-UART-";
+
+/// Selects the device to be connected among the discovered bridges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Device id given by the platform: MAC address on Linux, UUID on macOS. On Windows
+    /// the id ending with `-` and the MAC address is also matched.
+    Address(String),
+    ExactName(String),
+    NamePrefix(String),
+    /// Hex digits at the end of the firmware's `RTL-UART-%02X%02X%02X` name,
+    /// e.g. `02E000` or `E000`; `:` and `-` separators are ignored.
+    MacSuffix(String),
+    /// The first bridge found.
+    FirstFound,
+}
+
+impl DeviceSelector {
+    /// Whether the first match can be taken without checking for other matching devices.
+    pub(crate) fn is_unique(&self) -> bool {
+        matches!(self, Self::Address(_) | Self::FirstFound)
+    }
+
+    pub(crate) fn matches(&self, id: &str, name: Option<&str>) -> bool {
+        match self {
+            Self::Address(addr) => {
+                let (id, addr) = (id.to_lowercase(), addr.to_lowercase());
+                id == addr || id.ends_with(&format!("-{addr}"))
+            }
+            Self::ExactName(s) => name == Some(s.as_str()),
+            Self::NamePrefix(s) => name.is_some_and(|name| name.starts_with(s.as_str())),
+            Self::MacSuffix(s) => {
+                let suffix: String = s
+                    .chars()
+                    .filter(|c| *c != ':' && *c != '-')
+                    .collect::<String>()
+                    .to_uppercase();
+                name.and_then(|name| name.strip_prefix(NAME_PREFIX_FIRMWARE))
+                    .is_some_and(|hex| !suffix.is_empty() && hex.to_uppercase().ends_with(&suffix))
+            }
+            Self::FirstFound => true,
+        }
+    }
+}
+
+impl From<&str> for DeviceSelector {
+    fn from(addr: &str) -> Self {
+        Self::Address(addr.to_string())
+    }
+}
+
+impl From<String> for DeviceSelector {
+    fn from(addr: String) -> Self {
+        Self::Address(addr)
+    }
+}
+
+/// Parses `addr:<id>`, `name:<name>`, `prefix:<name prefix>`, `mac:<hex suffix>` or `first`;
+/// other strings are taken as the device id.
+impl FromStr for DeviceSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.split_once(':') {
+            Some(("addr", v)) => Self::Address(v.to_string()),
+            Some(("name", v)) => Self::ExactName(v.to_string()),
+            Some(("prefix", v)) => Self::NamePrefix(v.to_string()),
+            Some(("mac", v)) => Self::MacSuffix(v.to_string()),
+            _ if s == "first" => Self::FirstFound,
+            _ => Self::Address(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(v) => write!(f, "addr:{v}"),
+            Self::ExactName(v) => write!(f, "name:{v}"),
+            Self::NamePrefix(v) => write!(f, "prefix:{v}"),
+            Self::MacSuffix(v) => write!(f, "mac:{v}"),
+            Self::FirstFound => f.write_str("first"),
+        }
+    }
+}
+
+/// Selects the bluetooth adapter to be used.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AdapterSelector {
+    /// The default adapter of the system.
+    #[default]
+    Default,
+    /// Adapter name like `hci1`; supported on Linux only.
+    Name(String),
+    /// Index in the adapters sorted by name; only 0 is supported on platforms other than Linux.
+    Index(usize),
+}
+
+/// Parses `default`, an index number, or an adapter name.
+impl FromStr for AdapterSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(if s == "default" {
+            Self::Default
+        } else if let Ok(i) = s.parse() {
+            Self::Index(i)
+        } else {
+            Self::Name(s.to_string())
+        })
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::Name(v) => f.write_str(v),
+            Self::Index(i) => write!(f, "{i}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "00:E0:02:12:00:54";
+    const NAME: &str = "RTL-UART-02E000";
+
+    #[test]
+    fn address() {
+        assert!(DeviceSelector::from("00:e0:02:12:00:54").matches(ID, None));
+        // Windows
+        let id = "BluetoothLE#BluetoothLEd8:12:65:88:77:66-00:e0:02:12:00:54";
+        assert!(DeviceSelector::from(ID).matches(id, Some(NAME)));
+        assert!(!DeviceSelector::from("00:E0:02:12:00:55").matches(ID, Some(NAME)));
+    }
+
+    #[test]
+    fn names() {
+        let exact = DeviceSelector::ExactName(NAME.to_string());
+        assert!(exact.matches(ID, Some(NAME)));
+        assert!(!exact.matches(ID, Some("RTL-UART-02E0001")));
+        assert!(!exact.matches(ID, None));
+
+        let prefix = DeviceSelector::NamePrefix("RTL-UART-02".to_string());
+        assert!(prefix.matches(ID, Some(NAME)));
+        assert!(!prefix.matches(ID, Some("RTL-UART-03E000")));
+        assert!(!prefix.matches(ID, None));
+    }
+
+    #[test]
+    fn mac_suffix() {
+        for s in ["02E000", "e000", "02:E0:00", "E0-00"] {
+            assert!(DeviceSelector::MacSuffix(s.to_string()).matches(ID, Some(NAME)));
+        }
+        for s in ["E001", "", ":"] {
+            assert!(!DeviceSelector::MacSuffix(s.to_string()).matches(ID, Some(NAME)));
+        }
+        // only the firmware's names are matched
+        let suffix = DeviceSelector::MacSuffix("E000".to_string());
+        assert!(!suffix.matches(ID, Some("OTHER-02E000")));
+        assert!(!suffix.matches(ID, None));
+    }
+
+    #[test]
+    fn first_found() {
+        assert!(DeviceSelector::FirstFound.matches(ID, None));
+        assert!(DeviceSelector::FirstFound.is_unique());
+        assert!(!DeviceSelector::NamePrefix("RTL".to_string()).is_unique());
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in [
+            "addr:AA",
+            "name:RTL-UART-02E000",
+            "prefix:RTL",
+            "mac:E000",
+            "first",
+        ] {
+            let selector: DeviceSelector = s.parse().unwrap();
+            assert_eq!(selector.to_string(), s);
+        }
+        let selector: DeviceSelector = ID.parse().unwrap();
+        assert_eq!(selector, DeviceSelector::Address(ID.to_string()));
+    }
+}
@@ -0,0 +1,203 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! `serialport::SerialPort` implementation, for tools written against the `serialport` crate.
+
+use std::time::Duration;
+
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, Result, SerialPort, StopBits,
+};
+
+use crate::{BleSerial, BleSerialError, LinkHandle};
+
+// the UART of the bridge is fixed to 8N1 without flow control (see `uart.c`)
+
+impl From<BleSerialError> for Error {
+    fn from(e: BleSerialError) -> Self {
+        let kind = match e {
+            BleSerialError::AdapterNotFound
+            | BleSerialError::AdapterPoweredOff
+            | BleSerialError::DeviceNotFound
+            | BleSerialError::Disconnected => ErrorKind::NoDevice,
+            BleSerialError::BaudRejected { .. } => ErrorKind::InvalidInput,
+            _ => ErrorKind::Io(std::io::Error::from(e.clone()).kind()),
+        };
+        Error::new(kind, e.to_string())
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{what} is not supported by the BLE UART bridge"),
+    )
+}
+
+impl SerialPort for BleSerial {
+    /// Name of the connected device.
+    fn name(&self) -> Option<String> {
+        self.device_name()
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(LinkHandle::baud_rate(self).ok_or(BleSerialError::Disconnected)?)
+    }
+
+    fn data_bits(&self) -> Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        BleSerial::set_baud_rate(self, baud_rate)?;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        match data_bits {
+            DataBits::Eight => Ok(()),
+            _ => Err(unsupported("data bits other than 8")),
+        }
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
+        match flow_control {
+            FlowControl::None => Ok(()),
+            _ => Err(unsupported("flow control")),
+        }
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> Result<()> {
+        match parity {
+            Parity::None => Ok(()),
+            _ => Err(unsupported("parity")),
+        }
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
+        match stop_bits {
+            StopBits::One => Ok(()),
+            _ => Err(unsupported("stop bits other than 1")),
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> Result<()> {
+        Err(unsupported("RTS control"))
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> Result<()> {
+        Err(unsupported("DTR control"))
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        Err(unsupported("CTS reading"))
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        Err(unsupported("DSR reading"))
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        Err(unsupported("RI reading"))
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        Err(unsupported("CD reading"))
+    }
+
+    fn bytes_to_read(&self) -> Result<u32> {
+        let lck_res = self.res.lock().map_err(|_| BleSerialError::Poisoned)?;
+        Ok(lck_res.buf_read.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> Result<u32> {
+        Ok(LinkHandle::bytes_to_write(self) as u32)
+    }
+
+    /// Only the read buffer is cleared; data queued for writing is left alone
+    /// and sent anyway, since it may be already received by the device.
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
+        if let ClearBuffer::Input | ClearBuffer::All = buffer_to_clear {
+            self.drain_read_buf();
+        }
+        Ok(())
+    }
+
+    /// `BleSerial` owns the background task, so it can't be cloned.
+    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
+        Err(unsupported("cloning the port"))
+    }
+
+    fn set_break(&self) -> Result<()> {
+        Err(unsupported("break signal"))
+    }
+
+    fn clear_break(&self) -> Result<()> {
+        Err(unsupported("break signal"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serialport::{ClearBuffer, SerialPort};
+
+    use crate::{
+        fake::{wait_until, FakePeripheral},
+        BleSerial, DeviceSelector,
+    };
+
+    #[test]
+    fn boxed_port() {
+        let fake = FakePeripheral::default();
+        fake.set_loopback(true);
+        let ble_ser = BleSerial::build_with_transport(
+            fake.transport(),
+            DeviceSelector::FirstFound,
+            Duration::from_millis(1000),
+        )
+        .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let mut port: Box<dyn SerialPort> = Box::new(ble_ser);
+
+        // what tools usually do after opening the port
+        port.clear(ClearBuffer::All).unwrap();
+        port.set_baud_rate(115200).unwrap();
+        assert_eq!(port.baud_rate().unwrap(), 115090);
+        assert_eq!(port.name().as_deref(), Some("RTL-UART-02E000"));
+
+        port.write_all(b"ping").unwrap();
+        port.flush().unwrap();
+        let mut buf = [0u8; 4];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        port.write_all(b"junk").unwrap();
+        port.flush().unwrap();
+        assert!(wait_until(|| port.bytes_to_read().unwrap() == 4));
+        port.clear(ClearBuffer::Input).unwrap();
+        assert_eq!(port.bytes_to_read().unwrap(), 0);
+    }
+}
@@ -0,0 +1,119 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use crate::BleSerial;
+
+/// Reading half of [`BleSerial`] returned by [`BleSerial::split`].
+pub struct BleSerialReader {
+    ble_ser: Arc<BleSerial>,
+}
+
+/// Writing half of [`BleSerial`] returned by [`BleSerial::split`].
+pub struct BleSerialWriter {
+    ble_ser: Arc<BleSerial>,
+}
+
+impl BleSerial {
+    /// Splits into halves that can be used by separate threads, for reading and writing
+    /// at the same time without a lock; `Read` and `Write` are also implemented for `&BleSerial`.
+    pub fn split(self) -> (BleSerialReader, BleSerialWriter) {
+        let ble_ser = Arc::new(self);
+        (
+            BleSerialReader {
+                ble_ser: ble_ser.clone(),
+            },
+            BleSerialWriter { ble_ser },
+        )
+    }
+}
+
+impl BleSerialReader {
+    /// The shared `BleSerial`, for other operations like `set_baud_rate()`.
+    pub fn get_ref(&self) -> &BleSerial {
+        &self.ble_ser
+    }
+
+    /// Puts the halves back together; fails if `writer` comes from another `BleSerial`.
+    pub fn reunite(self, writer: BleSerialWriter) -> Result<BleSerial, (Self, BleSerialWriter)> {
+        if !Arc::ptr_eq(&self.ble_ser, &writer.ble_ser) {
+            return Err((self, writer));
+        }
+        drop(writer);
+        Ok(Arc::into_inner(self.ble_ser).expect("held by the halves only"))
+    }
+}
+
+impl BleSerialWriter {
+    /// The shared `BleSerial`, for other operations like `set_baud_rate()`.
+    pub fn get_ref(&self) -> &BleSerial {
+        &self.ble_ser
+    }
+}
+
+impl Read for BleSerialReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.ble_ser).read(buf)
+    }
+}
+
+impl Write for BleSerialWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.ble_ser).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.ble_ser).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        fake::{wait_until, FakePeripheral},
+        BleSerialBuilder, DeviceSelector,
+    };
+
+    #[test]
+    fn split_and_reunite() {
+        let fake = FakePeripheral::default();
+        fake.set_loopback(true);
+        let ble_ser = BleSerialBuilder::new(DeviceSelector::FirstFound)
+            .read_timeout(Duration::from_secs(5))
+            .build_with_transport(fake.transport())
+            .unwrap();
+        assert!(wait_until(|| ble_ser.is_connected()));
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let (mut reader, mut writer) = ble_ser.split();
+        let reading = thread::spawn(move || {
+            let mut buf = vec![0u8; 1000];
+            reader.read_exact(&mut buf).unwrap();
+            (reader, buf)
+        });
+        let data_clone = data.clone();
+        let writing = thread::spawn(move || {
+            for chunk in data_clone.chunks(100) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.flush().unwrap();
+            writer
+        });
+        let writer = writing.join().unwrap();
+        let (reader, received) = reading.join().unwrap();
+        assert_eq!(received, data);
+
+        let ble_ser = reader.reunite(writer).ok().unwrap();
+        assert!(ble_ser.is_connected());
+    }
+}
@@ -0,0 +1,36 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+use std::{collections::BTreeMap, time::Duration};
+
+/// Counters of the link, accumulated since `BleSerial` is built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub bytes_received: u64,
+    pub notifications_received: u64,
+    /// Received bytes discarded because the read buffer was full.
+    pub bytes_dropped: u64,
+    /// Bytes acknowledged by the device (or sent without response).
+    pub bytes_written: u64,
+    /// Failed write attempts that were retried.
+    pub write_retries: u64,
+    /// Chunks not sent after all attempts.
+    pub write_failures: u64,
+    /// Connections established after the first one.
+    pub reconnects: u64,
+    pub baud_set_attempts: u64,
+    pub baud_set_failures: u64,
+    /// Total time connected, including the current connection.
+    pub time_connected: Duration,
+    /// Count of notifications for each size in bytes; the firmware sends up to
+    /// `ATT_MTU - 3` bytes in each notification.
+    pub notification_sizes: BTreeMap<usize, u64>,
+}
+
+impl LinkStats {
+    pub(crate) fn record_notification(&mut self, len: usize) {
+        self.bytes_received += len as u64;
+        self.notifications_received += 1;
+        *self.notification_sizes.entry(len).or_insert(0) += 1;
+    }
+}
@@ -0,0 +1,230 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! TCP server of `--tcp-listen`, serving one client at a time in raw or RFC 2217 mode.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+use rtl8762c_ble_uart_host::{BleSerial, BleSerialEvent};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::rfc2217::{self, Rfc2217};
+
+const CLIENT_WRITE_TIMEOUT_MS: u64 = 5000;
+
+type Client = Arc<Mutex<Option<TcpStream>>>;
+
+/// Listens on the address; the threads stop when `BleSerial` is dropped.
+pub struct TcpBridge {
+    addr: SocketAddr,
+}
+
+impl TcpBridge {
+    /// Starts listening and shuttling data between the client and `BleSerial`.
+    /// `events` is a receiver got from `BleSerial::subscribe()`.
+    pub fn start(
+        ble_ser: Weak<BleSerial>,
+        addr: &str,
+        rfc2217: bool,
+        events: broadcast::Receiver<BleSerialEvent>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let client: Client = Arc::new(Mutex::new(None));
+
+        let (ble_ser_weak, client_clone) = (ble_ser.clone(), client.clone());
+        thread::spawn(move || ble_to_tcp(ble_ser_weak, client_clone, rfc2217, events));
+        thread::spawn(move || accept_clients(ble_ser, listener, client, rfc2217));
+
+        Ok(Self { addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+fn accept_clients(ble_ser: Weak<BleSerial>, listener: TcpListener, client: Client, rfc2217: bool) {
+    for stream in listener.incoming() {
+        if ble_ser.strong_count() == 0 {
+            return;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or("?".to_string(), |a| a.to_string());
+        let mut lck_client = client.lock().unwrap();
+        if lck_client.is_some() {
+            // the serial port can't be shared by multiple clients
+            println!("BleSerial: TCP client {peer} rejected, another client is connected");
+            let _ = stream.shutdown(Shutdown::Both);
+            continue;
+        }
+        let Ok(stream_write) = stream.try_clone() else {
+            continue;
+        };
+        let _ =
+            stream_write.set_write_timeout(Some(Duration::from_millis(CLIENT_WRITE_TIMEOUT_MS)));
+        lck_client.replace(stream_write);
+        drop(lck_client);
+
+        println!("BleSerial: TCP client {peer} connected");
+        let (ble_ser, client) = (ble_ser.clone(), client.clone());
+        thread::spawn(move || {
+            tcp_to_ble(ble_ser, stream, &client, rfc2217);
+            if let Some(stream) = client.lock().unwrap().take() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            println!("BleSerial: TCP client {peer} disconnected");
+        });
+    }
+}
+
+fn ble_to_tcp(
+    ble_ser: Weak<BleSerial>,
+    client: Client,
+    rfc2217: bool,
+    mut events: broadcast::Receiver<BleSerialEvent>,
+) {
+    loop {
+        match events.blocking_recv() {
+            Ok(BleSerialEvent::Receive(_)) | Err(RecvError::Lagged(_)) => (),
+            Ok(_) => continue,
+            Err(RecvError::Closed) => return,
+        }
+        let Some(ble_ser) = ble_ser.upgrade() else {
+            return;
+        };
+        // received data is discarded when no client is connected
+        let data = ble_ser.drain_read_buf();
+        drop(ble_ser);
+        let data = if rfc2217 {
+            rfc2217::escape(&data)
+        } else {
+            data
+        };
+        if let Some(stream) = client.lock().unwrap().as_mut() {
+            if stream.write_all(&data).is_err() {
+                // `tcp_to_ble()` will return and remove the client
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+fn tcp_to_ble(ble_ser: Weak<BleSerial>, mut stream: TcpStream, client: &Client, rfc2217: bool) {
+    let mut session = rfc2217.then(Rfc2217::new);
+    if let Some(session) = &session {
+        send_reply(client, &session.greeting());
+    }
+    let mut buf = [0u8; 4096];
+    loop {
+        let cnt = match stream.read(&mut buf) {
+            Ok(0) => return,
+            Ok(cnt) => cnt,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let Some(ble_ser) = ble_ser.upgrade() else {
+            return;
+        };
+        let data = if let Some(session) = session.as_mut() {
+            let (data, reply) = session.feed(&buf[..cnt], &*ble_ser);
+            send_reply(client, &reply);
+            data
+        } else {
+            buf[..cnt].to_vec()
+        };
+        if data.is_empty() {
+            continue;
+        }
+        if let Err(e) = (&*ble_ser).write_all(&data) {
+            println!("BleSerial: TCP data not sent: {e}");
+        }
+    }
+}
+
+fn send_reply(client: &Client, reply: &[u8]) {
+    if reply.is_empty() {
+        return;
+    }
+    if let Some(stream) = client.lock().unwrap().as_mut() {
+        let _ = stream.write_all(reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rtl8762c_ble_uart_host::{
+        fake::{wait_until, FakePeripheral},
+        DeviceSelector,
+    };
+
+    use super::*;
+
+    // returns the bridge with a client connected to it
+    fn connect(fake: &FakePeripheral, rfc2217: bool) -> (Arc<BleSerial>, TcpBridge, TcpStream) {
+        let ble_ser = Arc::new(
+            BleSerial::build_with_transport(
+                fake.transport(),
+                DeviceSelector::FirstFound,
+                Duration::from_secs(1),
+            )
+            .unwrap(),
+        );
+        assert!(wait_until(|| ble_ser.is_connected()));
+        let events = ble_ser.subscribe().unwrap();
+        let bridge =
+            TcpBridge::start(Arc::downgrade(&ble_ser), "127.0.0.1:0", rfc2217, events).unwrap();
+        let stream = TcpStream::connect(bridge.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (ble_ser, bridge, stream)
+    }
+
+    // reads from the client until the received data ends with `expected`
+    fn read_until_end(stream: &mut TcpStream, expected: &[u8]) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while !received.ends_with(expected) {
+            let cnt = stream.read(&mut buf).unwrap();
+            assert!(cnt > 0, "connection closed, received {received:?}");
+            received.extend_from_slice(&buf[..cnt]);
+        }
+        received
+    }
+
+    #[test]
+    fn raw_loopback() {
+        let fake = FakePeripheral::default();
+        fake.set_loopback(true);
+        let (_ble_ser, _bridge, mut stream) = connect(&fake, false);
+        stream.write_all(b"ping").unwrap();
+        assert_eq!(read_until_end(&mut stream, b"ping"), b"ping");
+    }
+
+    #[test]
+    fn rfc2217_set_baudrate() {
+        let fake = FakePeripheral::default();
+        let (_ble_ser, _bridge, mut stream) = connect(&fake, true);
+        // IAC SB COM-PORT-OPTION SET-BAUDRATE 115200 IAC SE
+        stream
+            .write_all(&[255, 250, 44, 1, 0, 1, 0xc2, 0, 255, 240])
+            .unwrap();
+        // the greeting comes first, then the actual baud rate
+        read_until_end(
+            &mut stream,
+            &[255, 250, 44, 101, 0, 1, 0xc1, 0x92, 255, 240],
+        );
+        assert_eq!(fake.baud_rate(), 115090);
+    }
+}
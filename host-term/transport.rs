@@ -0,0 +1,316 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! GATT transport abstraction used by the background BLE task, so that it can run
+//! against `bluest` or against the in-process fake peripheral of the `fake` feature.
+
+use std::{fmt, future::Future, pin::Pin};
+
+use futures::{Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::{AdapterSelector, UUID_DESC_CLIENT_CHAR_CONF};
+
+#[cfg(target_os = "linux")]
+pub use crate::bluer_transport::BluerTransport;
+
+pub type BoxStream<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + 'a>>;
+
+/// Error reported by a GATT transport operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportError(String);
+
+impl TransportError {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<bluest::Error> for TransportError {
+    fn from(e: bluest::Error) -> Self {
+        Self(e.to_string())
+    }
+}
+
+pub type TransportResult<T> = Result<T, TransportError>;
+
+/// Advertising packet received by [`GattTransport::scan`].
+#[derive(Debug, Clone)]
+pub struct Advertisement<D> {
+    pub device: D,
+    pub local_name: Option<String>,
+    pub rssi: Option<i16>,
+    /// `None` if the backend doesn't report it.
+    pub is_connectable: Option<bool>,
+}
+
+/// Bluetooth adapter: device discovery and connection.
+pub trait GattTransport: Send + Sync + 'static {
+    type Device: GattDevice;
+
+    /// Blocks until the adapter is available.
+    fn wait_available(&self) -> impl Future<Output = TransportResult<()>> + Send;
+
+    /// Stream of adapter state changes: `true` for powered on, `false` for powered off.
+    /// It ends when the adapter is removed.
+    fn adapter_events(&self) -> impl Future<Output = TransportResult<BoxStream<'_, bool>>> + Send;
+
+    /// Finds devices providing any service in `services`, connected devices first.
+    fn discover_devices<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> impl Future<Output = TransportResult<BoxStream<'a, TransportResult<Self::Device>>>> + Send + 'a;
+
+    /// Scans for advertising packets including any service in `services`.
+    fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> impl Future<Output = TransportResult<BoxStream<'a, Advertisement<Self::Device>>>> + Send + 'a;
+
+    fn connect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = TransportResult<()>> + Send;
+
+    /// Called when the device connected by `connect_device()` is no longer used, after
+    /// the connection is broken or when the background task is stopped.
+    fn release_device(&self, _device: &Self::Device) {}
+}
+
+/// Remote device found by [`GattTransport::discover_devices`].
+pub trait GattDevice: Clone + Send + Sync + 'static {
+    type Characteristic: GattCharacteristic;
+
+    /// Platform-dependent identifier (a MAC address on Linux, a UUID on macOS).
+    fn id(&self) -> String;
+
+    fn name(&self) -> impl Future<Output = TransportResult<String>> + Send;
+
+    fn is_connected(&self) -> impl Future<Output = bool> + Send;
+
+    /// Discovers services and returns the characteristics of `service`,
+    /// or `None` if the device doesn't provide it.
+    fn service_characteristics(
+        &self,
+        service: Uuid,
+    ) -> impl Future<Output = TransportResult<Option<Vec<Self::Characteristic>>>> + Send;
+}
+
+pub trait GattCharacteristic: Clone + Send + Sync + 'static {
+    fn uuid(&self) -> Uuid;
+
+    fn read(&self) -> impl Future<Output = TransportResult<Vec<u8>>> + Send;
+
+    fn write(&self, data: &[u8]) -> impl Future<Output = TransportResult<()>> + Send;
+
+    fn write_without_response(
+        &self,
+        data: &[u8],
+    ) -> impl Future<Output = TransportResult<()>> + Send;
+
+    /// Maximum length of a single write, which is ATT_MTU - 3.
+    fn max_write_len(&self) -> impl Future<Output = TransportResult<usize>> + Send;
+
+    /// Enables notification, failing if the device refuses it; the returned stream ends
+    /// when the connection is broken.
+    fn notify(
+        &self,
+    ) -> impl Future<Output = TransportResult<BoxStream<'static, TransportResult<Vec<u8>>>>> + Send;
+}
+
+/// Transport backed by the system's Bluetooth adapter through `bluest`.
+#[derive(Clone)]
+pub struct BluestTransport {
+    adapter: bluest::Adapter,
+}
+
+impl BluestTransport {
+    /// Opens the default Bluetooth adapter of the system.
+    pub async fn default_adapter() -> Option<Self> {
+        bluest::Adapter::default()
+            .await
+            .map(|adapter| Self { adapter })
+    }
+
+    /// Opens the adapter chosen by `selector`. Only the default adapter is accessible
+    /// through `bluest`, which is also taken as the adapter of index 0.
+    pub async fn open(selector: &AdapterSelector) -> Option<Self> {
+        match selector {
+            AdapterSelector::Default | AdapterSelector::Index(0) => Self::default_adapter().await,
+            _ => None,
+        }
+    }
+}
+
+/// Work to be done with the system's transport, whose type depends on the adapter.
+pub(crate) trait WithTransport {
+    type Output;
+
+    fn run<T: GattTransport>(self, transport: T) -> impl Future<Output = Self::Output> + Send;
+}
+
+/// Opens the system's adapter chosen by `selector` through `bluest`, or through `bluer`
+/// on Linux if `bluest` can't access it, and runs `work` with it. `work` is given back
+/// if the adapter is not found.
+pub(crate) async fn with_system_adapter<W: WithTransport>(
+    selector: &AdapterSelector,
+    work: W,
+) -> Result<W::Output, W> {
+    if let Some(transport) = BluestTransport::open(selector).await {
+        return Ok(work.run(transport).await);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(transport) = BluerTransport::open(selector).await {
+        return Ok(work.run(transport).await);
+    }
+    Err(work)
+}
+
+impl GattTransport for BluestTransport {
+    type Device = bluest::Device;
+
+    async fn wait_available(&self) -> TransportResult<()> {
+        Ok(self.adapter.wait_available().await?)
+    }
+
+    async fn adapter_events(&self) -> TransportResult<BoxStream<'_, bool>> {
+        let stream = self.adapter.events().await?;
+        Ok(Box::pin(stream.filter_map(|evt| async move {
+            match evt {
+                Ok(bluest::AdapterEvent::Available) => Some(true),
+                Ok(bluest::AdapterEvent::Unavailable) => Some(false),
+                Err(_) => None,
+            }
+        })))
+    }
+
+    async fn discover_devices<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, TransportResult<bluest::Device>>> {
+        let stream = self.adapter.discover_devices(services).await?;
+        Ok(Box::pin(stream.map(|r| r.map_err(TransportError::from))))
+    }
+
+    async fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> TransportResult<BoxStream<'a, Advertisement<bluest::Device>>> {
+        let stream = self.adapter.scan(services).await?;
+        Ok(Box::pin(stream.map(|adv_dev| Advertisement {
+            device: adv_dev.device,
+            local_name: adv_dev.adv_data.local_name,
+            rssi: adv_dev.rssi,
+            is_connectable: Some(adv_dev.adv_data.is_connectable),
+        })))
+    }
+
+    async fn connect_device(&self, device: &bluest::Device) -> TransportResult<()> {
+        Ok(self.adapter.connect_device(device).await?)
+    }
+}
+
+impl GattDevice for bluest::Device {
+    type Characteristic = bluest::Characteristic;
+
+    fn id(&self) -> String {
+        bluest::Device::id(self).to_string()
+    }
+
+    async fn name(&self) -> TransportResult<String> {
+        Ok(self.name_async().await?)
+    }
+
+    async fn is_connected(&self) -> bool {
+        bluest::Device::is_connected(self).await
+    }
+
+    async fn service_characteristics(
+        &self,
+        service: Uuid,
+    ) -> TransportResult<Option<Vec<bluest::Characteristic>>> {
+        self.discover_services().await?;
+        let services = self.services().await?;
+        let Some(service) = services.iter().find(|serv| serv.uuid() == service) else {
+            return Ok(None);
+        };
+        Ok(Some(service.characteristics().await?))
+    }
+}
+
+impl GattCharacteristic for bluest::Characteristic {
+    fn uuid(&self) -> Uuid {
+        bluest::Characteristic::uuid(self)
+    }
+
+    async fn read(&self) -> TransportResult<Vec<u8>> {
+        Ok(bluest::Characteristic::read(self).await?)
+    }
+
+    async fn write(&self, data: &[u8]) -> TransportResult<()> {
+        Ok(bluest::Characteristic::write(self, data).await?)
+    }
+
+    async fn write_without_response(&self, data: &[u8]) -> TransportResult<()> {
+        Ok(bluest::Characteristic::write_without_response(self, data).await?)
+    }
+
+    async fn max_write_len(&self) -> TransportResult<usize> {
+        Ok(self.max_write_len_async().await?)
+    }
+
+    async fn notify(&self) -> TransportResult<BoxStream<'static, TransportResult<Vec<u8>>>> {
+        let desc_char_conf = self
+            .descriptors()
+            .await?
+            .into_iter()
+            .find(|d| d.uuid() == UUID_DESC_CLIENT_CHAR_CONF)
+            .ok_or_else(|| TransportError::new("client characteristic configuration not found"))?;
+        if desc_char_conf.write(&[0x01, 0x00]).await.is_err() {
+            // enable notification
+            debug!("BluestTransport: failed to write conf desc of the characteristic.");
+        }
+
+        // the stream returned by `bluest` borrows the characteristic
+        let ch = self.clone();
+        subscribed(Box::pin(async_stream::stream! {
+            let mut stream_notify = match ch.notify().await {
+                Ok(s) => s,
+                Err(e) => {
+                    yield Err(TransportError::from(e));
+                    return;
+                }
+            };
+            yield Ok(None);
+            while let Some(item) = stream_notify.next().await {
+                yield item.map(Some).map_err(TransportError::from);
+            }
+        }))
+        .await
+    }
+}
+
+/// Waits for the first item of a notification stream that owns the characteristic,
+/// which is `Ok(None)` once the notification is enabled, so that a failure of the
+/// subscription is returned here instead of ending the stream later.
+pub(crate) async fn subscribed(
+    mut stream: BoxStream<'static, TransportResult<Option<Vec<u8>>>>,
+) -> TransportResult<BoxStream<'static, TransportResult<Vec<u8>>>> {
+    match stream.next().await {
+        Some(Ok(None)) => {
+            Ok(Box::pin(stream.filter_map(|item| {
+                futures::future::ready(item.transpose())
+            })))
+        }
+        Some(Err(e)) => Err(e),
+        _ => Err(TransportError::new("notification stream ended")),
+    }
+}
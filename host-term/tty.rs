@@ -0,0 +1,241 @@
+// by wuwbobo2021 <wuwbobo@outlook.com>
+// for use with <https://github.com/wuwbobo2021/rtl8762c-ble-uart>
+
+//! Interactive mode of the terminal: the console is put in raw mode and every
+//! keystroke is sent immediately, like picocom. Ctrl-A is the escape key.
+
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal,
+};
+
+use rtl8762c_ble_uart_host::BleSerial;
+
+use crate::eol::TxEol;
+
+const HELP: &str = "\
+*** Ctrl-A is the escape key, followed by:
+***   Ctrl-X  quit
+***   Ctrl-B  change the baud rate
+***   Ctrl-T  toggle hex display of received data
+***   Ctrl-S  send a file
+***   Ctrl-A  send Ctrl-A itself
+***   Ctrl-H  show this help";
+
+static RAW_TTY: AtomicBool = AtomicBool::new(false);
+
+/// Prints lines of messages, which are still aligned when the console is in raw mode.
+pub fn print_status(msg: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = if RAW_TTY.load(Ordering::Relaxed) {
+        write!(
+            stdout,
+            "{}\r\n",
+            msg.trim_end_matches('\n').replace('\n', "\r\n")
+        )
+    } else {
+        writeln!(stdout, "{}", msg.trim_end_matches('\n'))
+    };
+    let _ = stdout.flush();
+}
+
+// restores the console even if the input loop fails
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        RAW_TTY.store(true, Ordering::Relaxed);
+        Ok(Self)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        RAW_TTY.store(false, Ordering::Relaxed);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs until Ctrl-A Ctrl-X is pressed. `eol` is sent for the Enter key;
+/// `hex_display` is toggled by Ctrl-A Ctrl-T.
+pub fn run_interactive(
+    ble_ser: &BleSerial,
+    eol: TxEol,
+    hex_display: &AtomicBool,
+) -> io::Result<()> {
+    let _guard = RawModeGuard::enable()?;
+    print_status("*** interactive mode, Ctrl-A Ctrl-H for help, Ctrl-A Ctrl-X to quit");
+
+    let mut escaped = false;
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        if !escaped {
+            if is_ctrl(&key, 'a') {
+                escaped = true;
+            } else {
+                send(ble_ser, &key_bytes(&key, eol));
+            }
+            continue;
+        }
+
+        escaped = false;
+        let cmd = match key.code {
+            KeyCode::Char(c) => c.to_ascii_lowercase(),
+            KeyCode::Backspace => 'h', // Ctrl-H on some consoles
+            _ => '\0',
+        };
+        match cmd {
+            'x' | 'q' => return Ok(()),
+            'b' => change_baud(ble_ser)?,
+            't' => {
+                let hex = !hex_display.fetch_xor(true, Ordering::Relaxed);
+                print_status(&format!(
+                    "*** hex display {}",
+                    if hex { "on" } else { "off" }
+                ));
+            }
+            's' => send_file(ble_ser)?,
+            'a' => send(ble_ser, &[0x01]),
+            'h' | '?' => print_status(HELP),
+            _ => print_status("*** unknown command, Ctrl-A Ctrl-H for help"),
+        }
+    }
+}
+
+fn is_ctrl(key: &KeyEvent, c: char) -> bool {
+    key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char(c)
+}
+
+// bytes sent by a VT100-like terminal for the key
+fn key_bytes(key: &KeyEvent, eol: TxEol) -> Vec<u8> {
+    let seq: &[u8] = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            let b = match c {
+                'a'..='z' => c as u8 - b'a' + 1,
+                '4'..='7' => c as u8 - b'4' + 0x1C,
+                ' ' | '@' | '2' => 0,
+                '[' | '3' => 0x1B,
+                '\\' => 0x1C,
+                ']' => 0x1D,
+                '^' => 0x1E,
+                '_' => 0x1F,
+                _ => return Vec::new(),
+            };
+            return vec![b];
+        }
+        KeyCode::Char(c) => {
+            let mut buf = [0u8; 4];
+            let s = c.encode_utf8(&mut buf).as_bytes();
+            if key.modifiers.contains(KeyModifiers::ALT) {
+                return [&[0x1B], s].concat();
+            }
+            return s.to_vec();
+        }
+        KeyCode::Enter => return eol.apply(""),
+        KeyCode::Backspace => b"\x7F",
+        KeyCode::Tab => b"\t",
+        KeyCode::BackTab => b"\x1B[Z",
+        KeyCode::Esc => b"\x1B",
+        KeyCode::Up => b"\x1B[A",
+        KeyCode::Down => b"\x1B[B",
+        KeyCode::Right => b"\x1B[C",
+        KeyCode::Left => b"\x1B[D",
+        KeyCode::Home => b"\x1B[H",
+        KeyCode::End => b"\x1B[F",
+        KeyCode::Insert => b"\x1B[2~",
+        KeyCode::Delete => b"\x1B[3~",
+        KeyCode::PageUp => b"\x1B[5~",
+        KeyCode::PageDown => b"\x1B[6~",
+        KeyCode::F(1) => b"\x1BOP",
+        KeyCode::F(2) => b"\x1BOQ",
+        KeyCode::F(3) => b"\x1BOR",
+        KeyCode::F(4) => b"\x1BOS",
+        _ => b"",
+    };
+    seq.to_vec()
+}
+
+fn send(mut ble_ser: &BleSerial, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    if let Err(e) = ble_ser.write_all(data) {
+        print_status(&format!("*** not sent: {e}"));
+    }
+}
+
+fn change_baud(ble_ser: &BleSerial) -> io::Result<()> {
+    let Some(input) = prompt("baud rate: ")? else {
+        return Ok(());
+    };
+    match input.trim().parse::<u32>() {
+        Ok(baud) if baud > 0 => match ble_ser.set_baud_rate(baud) {
+            Ok(b) => print_status(&format!("*** baud rate {b}")),
+            Err(e) => print_status(&format!("*** baud rate not set: {e}")),
+        },
+        _ => print_status("*** invalid baud rate"),
+    }
+    Ok(())
+}
+
+fn send_file(mut ble_ser: &BleSerial) -> io::Result<()> {
+    let Some(path) = prompt("file to send: ")? else {
+        return Ok(());
+    };
+    let data = match std::fs::read(path.trim()) {
+        Ok(data) => data,
+        Err(e) => {
+            print_status(&format!("*** can't read the file: {e}"));
+            return Ok(());
+        }
+    };
+    // received data is still printed while the file is being sent
+    match ble_ser.write_all(&data).and_then(|_| ble_ser.flush()) {
+        Ok(()) => print_status(&format!("*** {} bytes sent", data.len())),
+        Err(e) => print_status(&format!("*** file not sent: {e}")),
+    }
+    Ok(())
+}
+
+// reads a line in raw mode; returns `None` if cancelled by Esc
+fn prompt(msg: &str) -> io::Result<Option<String>> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\r\n*** {msg}")?;
+    stdout.flush()?;
+    let mut input = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => break,
+            KeyCode::Esc => {
+                print_status("");
+                return Ok(None);
+            }
+            KeyCode::Backspace if input.pop().is_some() => write!(stdout, "\x08 \x08")?,
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                input.push(c);
+                write!(stdout, "{c}")?;
+            }
+            _ => (),
+        }
+        stdout.flush()?;
+    }
+    print_status("");
+    Ok(Some(input))
+}